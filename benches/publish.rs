@@ -0,0 +1,78 @@
+//! Measures how fast listing changes get published, batched in one pipeline against one
+//! round trip per change like before
+//!
+//! Needs a scratch redis, the changes are published on its listing channel and stream:
+//!
+//! ```sh
+//! TEST_REDIS_URL=redis://127.0.0.1:6379/15 cargo bench --bench publish
+//! ```
+
+use std::time::Instant;
+
+use pricer::{
+    event::{listing_key, UniversalItem, UniversalListing},
+    types::StrIntValue,
+    Database, ListingChange,
+};
+
+/// Changes per batch, a busy websocket frame has about this many listings
+const BATCH_SIZE: usize = 100;
+const BATCHES: usize = 50;
+
+fn changes() -> Vec<ListingChange> {
+    (0..BATCH_SIZE as u64)
+        .map(|asset_id| {
+            let listing = UniversalListing {
+                id: Some(format!("440_{}", asset_id)),
+                steamid: "76561198000000000".to_owned(),
+                details: Some("bench listing".to_owned()),
+                intent: "sell".to_owned(),
+                price: 60.11,
+                bumped_at: 1_700_000_000,
+                item: UniversalItem {
+                    id: Some(StrIntValue::Int(asset_id)),
+                    name: Some("Mann Co. Supply Crate Key".to_owned()),
+                    defindex: 5021,
+                },
+            };
+            let key = listing_key(5021, listing.id.as_deref().unwrap());
+            ListingChange::update(&key, Some("Mann Co. Supply Crate Key"), listing)
+        })
+        .collect()
+}
+
+fn report(name: &str, started: Instant) {
+    let elapsed = started.elapsed();
+    let total = BATCH_SIZE * BATCHES;
+    println!(
+        "{:<10} {} changes in {:?}, {:.0} changes/s",
+        name,
+        total,
+        elapsed,
+        total as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let Ok(url) = std::env::var("TEST_REDIS_URL") else {
+        eprintln!("TEST_REDIS_URL not set, skipping");
+        return;
+    };
+    let db = Database::connect(&url).await;
+
+    // one round trip per change, the way the changes were published before
+    let started = Instant::now();
+    for _ in 0..BATCHES {
+        for change in changes() {
+            db.publish_listing_changes(vec![change]).await;
+        }
+    }
+    report("per change", started);
+
+    let started = Instant::now();
+    for _ in 0..BATCHES {
+        db.publish_listing_changes(changes()).await;
+    }
+    report("pipelined", started);
+}
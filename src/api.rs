@@ -0,0 +1,668 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "export")]
+use axum::{extract::Query, http::header, response::Response};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Path, State, WebSocketUpgrade,
+    },
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    db::Database,
+    feed::Feed,
+    guard::{BreakerReason, BreakerTrip},
+    items::TrackedItem,
+    notifier::Notifier,
+    pricing::{
+        get_item_price, get_item_prices, get_key_price, ItemCurrencies, PriceConfig, PriceOverride,
+    },
+    socketio,
+};
+#[cfg(feature = "export")]
+use crate::{
+    export::{
+        listing_rows, price_rows, select_items, write_rows, ExportError, ExportFormat, ExportRow,
+    },
+    types::parse_time,
+};
+
+type ApiResponse = (StatusCode, Json<Value>);
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Database,
+    pub feed: Feed,
+    pub config: PriceConfig,
+    /// Publishes the prices approved from the review queue and the override changes
+    pub notifier: Notifier,
+    /// Bearer token of the changing routes, the API is read only without it
+    pub api_token: Option<String>,
+}
+
+/// `API_TOKEN`, an empty token counts as unset
+pub fn api_token_from_env() -> Option<String> {
+    std::env::var("API_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Messages the feed clients can send to pick the skus they want to receive
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum ClientMessage {
+    Subscribe { skus: Vec<String> },
+    Unsubscribe { skus: Vec<String> },
+}
+
+pub fn router(state: AppState) -> Router {
+    let router = Router::new()
+        .route("/items", get(get_items))
+        .route("/items/:sku", get(get_item).post(check_item))
+        .route("/overrides", get(get_overrides))
+        .route(
+            "/overrides/:sku",
+            get(get_override).put(put_override).delete(delete_override),
+        )
+        .route("/review", get(get_review))
+        .route("/review/:sku/approve", post(approve_price))
+        .route("/review/:sku/reject", post(reject_price))
+        .route("/breaker", get(get_breaker))
+        .route("/breaker/trip", post(trip_breaker))
+        .route("/breaker/reset", post(reset_breaker))
+        .route("/snapshots", get(get_snapshots))
+        .route("/snapshots/:sku", get(get_snapshot))
+        .route("/ws", get(ws_handler))
+        .route("/socket.io/", get(socketio::handler));
+
+    #[cfg(feature = "export")]
+    let router = router
+        .route("/export/listings", get(export_listings))
+        .route("/export/prices", get(export_prices));
+
+    router.with_state(state)
+}
+
+/// Serves the API on the given address until the process exits
+pub async fn serve(address: &str, state: AppState) {
+    let listener = match tokio::net::TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            panic!("Failed to bind the API to {}: {:?}", address, e);
+        }
+    };
+
+    info!("Serving the API on {}", address);
+
+    if let Err(e) = axum::serve(listener, router(state)).await {
+        error!("API server stopped: {:?}", e);
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> ApiResponse {
+    (
+        status,
+        Json(json!({ "success": false, "message": message })),
+    )
+}
+
+fn internal_error<E: std::fmt::Debug>(e: E) -> ApiResponse {
+    error!("Failed to handle API request: {:?}", e);
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+}
+
+async fn find_item(db: &Database, sku: &str) -> Result<TrackedItem, ApiResponse> {
+    match db.find_tracked_item(sku).await {
+        Ok(Some(item)) => Ok(item),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, "Item not found")),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// `GET /items`, the prices of all the tracked items that have a buy and sell price
+async fn get_items(State(state): State<AppState>) -> ApiResponse {
+    match get_item_prices(&state.db, &state.config).await {
+        Ok(prices) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "currency": null, "items": prices })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `GET /items/{sku}`, the price of a single item
+async fn get_item(State(state): State<AppState>, Path(sku): Path<String>) -> ApiResponse {
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    let key_price = match get_key_price(&state.db, &state.config).await {
+        Ok(key_price) => key_price,
+        Err(e) => return internal_error(e),
+    };
+
+    match get_item_price(&state.db, &state.config, &item, key_price).await {
+        Ok(Some(price)) => {
+            let mut value = serde_json::to_value(price).unwrap();
+            value["success"] = json!(true);
+            value["currency"] = Value::Null;
+            (StatusCode::OK, Json(value))
+        }
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "Item is not priced"),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `POST /items/{sku}`, requests a price check, tf2autobot calls this when it wants a fresh price
+///
+/// The item is priced again from its stored listings and published if it moved
+async fn check_item(State(state): State<AppState>, Path(sku): Path<String>) -> ApiResponse {
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    state
+        .notifier
+        .check_items(HashSet::from([item.name.clone()]))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(json!({ "success": true, "sku": item.sku_or_name(), "name": item.name })),
+    )
+}
+
+/// Body of `PUT /overrides/{sku}`, `expires_at` is a unix timestamp
+#[derive(Debug, Deserialize)]
+struct OverrideRequest {
+    buy: ItemCurrencies,
+    sell: ItemCurrencies,
+    author: String,
+    reason: String,
+    expires_at: Option<i64>,
+}
+
+/// Overrides, the review queue and the circuit breaker can only be changed with
+/// `Authorization: Bearer {API_TOKEN}`, without `API_TOKEN` they are read only
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), ApiResponse> {
+    let Some(token) = &state.api_token else {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "The API is read only, API_TOKEN is not set",
+        ));
+    };
+
+    let expected = format!("Bearer {}", token);
+    match headers.get("authorization") {
+        Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(error_response(
+            StatusCode::UNAUTHORIZED,
+            "Invalid API token",
+        )),
+    }
+}
+
+/// Compares the digests of both values, so the time taken depends on neither the length
+/// nor the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    Sha256::digest(a)
+        .iter()
+        .zip(Sha256::digest(b).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// `GET /overrides`, all the overrides that didn't expire
+async fn get_overrides(State(state): State<AppState>) -> ApiResponse {
+    match state.db.get_overrides().await {
+        Ok(overrides) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "overrides": overrides })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `GET /overrides/{sku}`, the override of a single item
+async fn get_override(State(state): State<AppState>, Path(sku): Path<String>) -> ApiResponse {
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    match state.db.get_override(&item.name).await {
+        Ok(Some(price)) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "override": price })),
+        ),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "Item is not overridden"),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `PUT /overrides/{sku}`, pins the price of the item, replacing its previous override
+async fn put_override(
+    State(state): State<AppState>,
+    Path(sku): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<OverrideRequest>,
+) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    let price = match PriceOverride::new(
+        &item.name,
+        request.buy,
+        request.sell,
+        &request.author,
+        &request.reason,
+        request.expires_at,
+    ) {
+        Ok(price) => price,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+    };
+
+    match state.notifier.set_override(&price).await {
+        Ok(()) => {
+            info!(
+                "{} overrode the price of {}: {}",
+                price.author, price.item, price.reason
+            );
+            (
+                StatusCode::OK,
+                Json(json!({ "success": true, "override": price })),
+            )
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `DELETE /overrides/{sku}`, goes back to the suggestion
+async fn delete_override(
+    State(state): State<AppState>,
+    Path(sku): Path<String>,
+    headers: HeaderMap,
+) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    match state.notifier.remove_override(&item.name).await {
+        Ok(true) => (StatusCode::OK, Json(json!({ "success": true }))),
+        Ok(false) => error_response(StatusCode::NOT_FOUND, "Item is not overridden"),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `GET /review`, the prices held by the price guard, oldest first
+async fn get_review(State(state): State<AppState>) -> ApiResponse {
+    match state.db.get_held_prices().await {
+        Ok(held) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "held": held })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `POST /review/{sku}/approve`, publishes the held price of the item
+async fn approve_price(
+    State(state): State<AppState>,
+    Path(sku): Path<String>,
+    headers: HeaderMap,
+) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    match state.notifier.approve(&item.name).await {
+        Ok(Some(held)) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "price": held.new })),
+        ),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "No price waits for review"),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `POST /review/{sku}/reject`, drops the held price of the item
+async fn reject_price(
+    State(state): State<AppState>,
+    Path(sku): Path<String>,
+    headers: HeaderMap,
+) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    match state.db.remove_held_price(&item.name).await {
+        Ok(true) => (StatusCode::OK, Json(json!({ "success": true }))),
+        Ok(false) => error_response(StatusCode::NOT_FOUND, "No price waits for review"),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Body of `POST /breaker/trip`
+#[derive(Debug, Deserialize)]
+struct TripRequest {
+    reason: String,
+}
+
+/// `GET /breaker`, whether the circuit breaker froze the prices
+async fn get_breaker(State(state): State<AppState>) -> ApiResponse {
+    match state.db.get_breaker().await {
+        Ok(trip) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "tripped": trip })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `POST /breaker/trip`, freezes all prices until the breaker is reset
+async fn trip_breaker(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<TripRequest>,
+) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    let trip = BreakerTrip::new(BreakerReason::Manual, request.reason);
+    if let Err(e) = state.db.trip_breaker(&trip).await {
+        return internal_error(e);
+    }
+
+    match state.db.get_breaker().await {
+        Ok(trip) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "tripped": trip })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `POST /breaker/reset`, publishes prices again
+async fn reset_breaker(State(state): State<AppState>, headers: HeaderMap) -> ApiResponse {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+
+    match state.db.reset_breaker().await {
+        Ok(_) => (StatusCode::OK, Json(json!({ "success": true }))),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `GET /snapshots`, when the snapshots of all the items were fetched and created
+async fn get_snapshots(State(state): State<AppState>) -> ApiResponse {
+    match state.db.get_snapshot_freshnesses().await {
+        Ok(snapshots) => (
+            StatusCode::OK,
+            Json(json!({ "success": true, "snapshots": snapshots })),
+        ),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `GET /snapshots/{sku}`, when the snapshot of a single item was fetched and created
+async fn get_snapshot(State(state): State<AppState>, Path(sku): Path<String>) -> ApiResponse {
+    let item = match find_item(&state.db, &sku).await {
+        Ok(item) => item,
+        Err(response) => return response,
+    };
+
+    match state.db.get_snapshot_freshness(&item.name).await {
+        Ok(snapshot) => {
+            let mut value = serde_json::to_value(snapshot).unwrap();
+            value["success"] = json!(true);
+            (StatusCode::OK, Json(value))
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Query of the export downloads, `skus` is comma separated and the times are unix
+/// timestamps or RFC 3339 dates
+#[cfg(feature = "export")]
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    format: Option<String>,
+    skus: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[cfg(feature = "export")]
+struct ExportRequest {
+    format: ExportFormat,
+    items: Vec<TrackedItem>,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[cfg(feature = "export")]
+fn export_error(e: ExportError) -> ApiResponse {
+    match e {
+        ExportError::UnknownSku(_) => error_response(StatusCode::NOT_FOUND, "Item not found"),
+        e => internal_error(e),
+    }
+}
+
+#[cfg(feature = "export")]
+async fn export_request(db: &Database, params: ExportParams) -> Result<ExportRequest, ApiResponse> {
+    let format = match params.format.as_deref() {
+        Some(name) => match ExportFormat::from_name(name) {
+            Some(format) => format,
+            None => return Err(error_response(StatusCode::BAD_REQUEST, "Unknown format")),
+        },
+        None => ExportFormat::Csv,
+    };
+
+    let parse = |value: Option<String>| match value {
+        Some(value) => match parse_time(&value) {
+            Some(time) => Ok(Some(time)),
+            None => Err(error_response(StatusCode::BAD_REQUEST, "Invalid time")),
+        },
+        None => Ok(None),
+    };
+    let from = parse(params.from)?;
+    let to = parse(params.to)?;
+
+    let skus: Vec<String> = params
+        .skus
+        .iter()
+        .flat_map(|skus| skus.split(','))
+        .map(|sku| sku.trim().to_owned())
+        .filter(|sku| !sku.is_empty())
+        .collect();
+    let items = select_items(db, &skus).await.map_err(export_error)?;
+
+    Ok(ExportRequest {
+        format,
+        items,
+        from,
+        to,
+    })
+}
+
+/// Serves the rows as a file named after the dataset
+#[cfg(feature = "export")]
+fn download<R: ExportRow>(rows: &[R], format: ExportFormat, dataset: &str) -> Response {
+    let mut body = Vec::new();
+    if let Err(e) = write_rows(rows, format, &mut body) {
+        return export_error(e).into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", dataset, format.name()),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// `GET /export/listings`, the stored listings that were bumped in the time range
+#[cfg(feature = "export")]
+async fn export_listings(
+    State(state): State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let request = match export_request(&state.db, params).await {
+        Ok(request) => request,
+        Err(response) => return response.into_response(),
+    };
+
+    match listing_rows(&state.db, &request.items, request.from, request.to).await {
+        Ok(rows) => download(&rows, request.format, "listings"),
+        Err(e) => export_error(e).into_response(),
+    }
+}
+
+/// `GET /export/prices`, the prices that were published in the time range
+#[cfg(feature = "export")]
+async fn export_prices(
+    State(state): State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let request = match export_request(&state.db, params).await {
+        Ok(request) => request,
+        Err(response) => return response.into_response(),
+    };
+
+    match price_rows(&state.db, &request.items, request.from, request.to).await {
+        Ok(rows) => download(&rows, request.format, "prices"),
+        Err(e) => export_error(e).into_response(),
+    }
+}
+
+async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+/// Streams the feed messages of the subscribed skus to the client
+async fn handle_socket(mut socket: WebSocket, state: AppState) {
+    let mut events = state.feed.subscribe();
+    // the feed is keyed by item name, the client subscribes by sku
+    let mut subscribed: HashSet<String> = HashSet::new();
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("Failed to read message from feed client: {:?}", e);
+                        return;
+                    }
+                };
+
+                let reply = match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Subscribe { skus }) => {
+                        let mut unknown = Vec::new();
+                        for sku in skus {
+                            match state.db.find_tracked_item(&sku).await {
+                                Ok(Some(item)) => {
+                                    subscribed.insert(item.name);
+                                }
+                                Ok(None) => unknown.push(sku),
+                                Err(e) => {
+                                    error!("Failed to look up sku {}: {:?}", sku, e);
+                                    unknown.push(sku);
+                                }
+                            }
+                        }
+
+                        json!({
+                            "type": "subscribed",
+                            "data": { "items": subscribed, "unknown": unknown },
+                        })
+                    }
+                    Ok(ClientMessage::Unsubscribe { skus }) => {
+                        for sku in skus {
+                            if let Ok(Some(item)) = state.db.find_tracked_item(&sku).await {
+                                subscribed.remove(&item.name);
+                            }
+                        }
+
+                        json!({
+                            "type": "subscribed",
+                            "data": { "items": subscribed, "unknown": [] },
+                        })
+                    }
+                    Err(e) => json!({
+                        "type": "error",
+                        "data": { "message": format!("Invalid message: {}", e) },
+                    }),
+                };
+
+                if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                    return;
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Feed client fell behind, skipped {} messages", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                if !subscribed.contains(&event.item) {
+                    continue;
+                }
+
+                if socket
+                    .send(Message::Text(event.message.as_ref().clone()))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
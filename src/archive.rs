@@ -0,0 +1,339 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// Number of records that can wait for the writer before recording slows down the caller
+const QUEUE_SIZE: usize = 4096;
+
+/// Prefix and extension of the archive files
+const FILE_PREFIX: &str = "archive-";
+const FILE_EXTENSION: &str = ".jsonl.gz";
+
+/// One line of the archive, the raw data exactly as it was received from backpack.tf
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "source", rename_all = "kebab-case")]
+pub enum ArchiveRecord {
+    Websocket {
+        /// Unix timestamp in milliseconds
+        received_at: i64,
+        frame: String,
+    },
+    Snapshot {
+        /// Unix timestamp in milliseconds
+        received_at: i64,
+        item: String,
+        body: String,
+    },
+}
+
+impl ArchiveRecord {
+    pub fn received_at(&self) -> i64 {
+        match self {
+            ArchiveRecord::Websocket { received_at, .. } => *received_at,
+            ArchiveRecord::Snapshot { received_at, .. } => *received_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveConfig {
+    pub dir: PathBuf,
+    /// How long one file is written to before a new one is started
+    pub rotate_every: Duration,
+    /// How long old files are kept
+    pub retention: Duration,
+}
+
+impl ArchiveConfig {
+    /// Reads `ARCHIVE_DIR`, `ARCHIVE_ROTATE_MINUTES` (default 60) and `ARCHIVE_RETENTION_DAYS`
+    /// (default 30), returns `None` if `ARCHIVE_DIR` isn't set
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("ARCHIVE_DIR").ok()?;
+
+        let rotate_minutes: u64 = match std::env::var("ARCHIVE_ROTATE_MINUTES") {
+            Ok(minutes) => minutes
+                .parse()
+                .expect("ARCHIVE_ROTATE_MINUTES is not a number"),
+            Err(_) => 60,
+        };
+
+        let retention_days: u64 = match std::env::var("ARCHIVE_RETENTION_DAYS") {
+            Ok(days) => days
+                .parse()
+                .expect("ARCHIVE_RETENTION_DAYS is not a number"),
+            Err(_) => 30,
+        };
+
+        Some(Self {
+            dir: PathBuf::from(dir),
+            rotate_every: Duration::from_secs(rotate_minutes.max(1) * 60),
+            retention: Duration::from_secs(retention_days * 86400),
+        })
+    }
+}
+
+/// Records raw websocket frames and snapshot responses to gzip compressed JSONL files
+///
+/// Writing happens on a blocking thread, recording only queues the record
+#[derive(Clone)]
+pub struct Recorder {
+    sender: mpsc::Sender<ArchiveRecord>,
+}
+
+impl Recorder {
+    pub fn new(config: ArchiveConfig) -> Self {
+        if let Err(e) = std::fs::create_dir_all(&config.dir) {
+            panic!(
+                "Failed to create the archive directory {:?}: {:?}",
+                config.dir, e
+            );
+        }
+
+        info!("Archiving raw events to {:?}", config.dir);
+
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || {
+            ArchiveWriter::new(config).run(receiver);
+        });
+
+        Self { sender }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        ArchiveConfig::from_env().map(Self::new)
+    }
+
+    pub async fn record_frame(&self, frame: &str) {
+        self.record(ArchiveRecord::Websocket {
+            received_at: Utc::now().timestamp_millis(),
+            frame: frame.to_owned(),
+        })
+        .await;
+    }
+
+    pub async fn record_snapshot(&self, item: &str, body: &str) {
+        self.record(ArchiveRecord::Snapshot {
+            received_at: Utc::now().timestamp_millis(),
+            item: item.to_owned(),
+            body: body.to_owned(),
+        })
+        .await;
+    }
+
+    async fn record(&self, record: ArchiveRecord) {
+        if self.sender.send(record).await.is_err() {
+            error!("Archive writer stopped, dropping record");
+        }
+    }
+}
+
+/// Returns the archive files in the directory, oldest first
+pub fn archive_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_EXTENSION))
+        })
+        .collect();
+
+    // the file names contain the start of their period, so they sort by time
+    files.sort();
+    Ok(files)
+}
+
+/// Replaces the directories with the archive files in them, oldest first
+pub fn expand_archive_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        match archive_files(path) {
+            Ok(dir_files) => files.extend(dir_files),
+            Err(e) => {
+                error!("Failed to list archive files in {:?}: {:?}", path, e);
+            }
+        }
+    }
+
+    files
+}
+
+/// Reads the records of an archive file in the order they were written
+///
+/// Lines that can't be parsed are skipped, a file that was cut off by a crash ends at the cut
+pub struct ArchiveReader {
+    path: PathBuf,
+    lines: Lines<BufReader<MultiGzDecoder<File>>>,
+}
+
+impl ArchiveReader {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            lines: BufReader::new(MultiGzDecoder::new(file)).lines(),
+        })
+    }
+}
+
+impl Iterator for ArchiveReader {
+    type Item = ArchiveRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Stopped reading archive file {:?}: {:?}", self.path, e);
+                    return None;
+                }
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(record) => return Some(record),
+                Err(e) => {
+                    warn!("Skipping invalid record in {:?}: {:?}", self.path, e);
+                }
+            }
+        }
+    }
+}
+
+struct ArchiveWriter {
+    config: ArchiveConfig,
+    /// Start of the period of the open file as a unix timestamp
+    period: i64,
+    file: Option<GzEncoder<BufWriter<File>>>,
+}
+
+impl ArchiveWriter {
+    fn new(config: ArchiveConfig) -> Self {
+        Self {
+            config,
+            period: 0,
+            file: None,
+        }
+    }
+
+    fn run(mut self, mut receiver: mpsc::Receiver<ArchiveRecord>) {
+        while let Some(record) = receiver.blocking_recv() {
+            self.write(&record);
+
+            // flush once the queue is drained so a crash loses as little as possible
+            if receiver.is_empty() {
+                if let Some(file) = &mut self.file {
+                    if let Err(e) = file.flush() {
+                        error!("Failed to flush the archive file: {:?}", e);
+                    }
+                }
+            }
+        }
+
+        self.close();
+    }
+
+    fn write(&mut self, record: &ArchiveRecord) {
+        let rotate_secs = self.config.rotate_every.as_secs() as i64;
+        let period = record.received_at() / 1000 / rotate_secs * rotate_secs;
+
+        if self.file.is_none() || period != self.period {
+            self.rotate(period);
+        }
+
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        let mut line = serde_json::to_string(record).unwrap();
+        line.push('\n');
+
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            error!("Failed to write to the archive file: {:?}", e);
+        }
+    }
+
+    fn rotate(&mut self, period: i64) {
+        self.close();
+        self.period = period;
+
+        let started = DateTime::<Utc>::from_timestamp(period, 0).unwrap_or_default();
+        let path = self.config.dir.join(format!(
+            "{}{}{}",
+            FILE_PREFIX,
+            started.format("%Y%m%dT%H%M%S"),
+            FILE_EXTENSION
+        ));
+
+        // reopening after a restart appends a new gzip member, which decoders read as one stream
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                info!("Archiving to {:?}", path);
+                self.file = Some(GzEncoder::new(BufWriter::new(file), Compression::default()));
+            }
+            Err(e) => {
+                error!("Failed to open the archive file {:?}: {:?}", path, e);
+            }
+        }
+
+        self.remove_expired();
+    }
+
+    fn close(&mut self) {
+        if let Some(file) = self.file.take() {
+            if let Err(e) = file.finish().and_then(|mut file| file.flush()) {
+                error!("Failed to finish the archive file: {:?}", e);
+            }
+        }
+    }
+
+    /// Deletes the files that weren't written to within the retention
+    fn remove_expired(&self) {
+        let files = match archive_files(&self.config.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Failed to list the archive files: {:?}", e);
+                return;
+            }
+        };
+
+        let now = SystemTime::now();
+        for path in files {
+            let modified = match std::fs::metadata(&path).and_then(|meta| meta.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+
+            let expired = now
+                .duration_since(modified)
+                .is_ok_and(|age| age > self.config.retention);
+
+            if expired {
+                match std::fs::remove_file(&path) {
+                    Ok(()) => info!("Removed expired archive file {:?}", path),
+                    Err(e) => warn!("Failed to remove archive file {:?}: {:?}", path, e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    archive::{expand_archive_paths, ArchiveReader, ArchiveRecord},
+    book::ListingBook,
+    event::{listing_key, DecodedFrame, Event, UniversalListing},
+    filter::ListingFilter,
+    pricing::PriceSuggestion,
+    types::ListingResponse,
+};
+
+/// A way of turning the listings of an item into a buy and sell price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// The live pricing logic, averages without outliers
+    Current,
+    /// Median price of each side
+    Median,
+    /// Highest buy and lowest sell listing
+    Best,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [Strategy::Current, Strategy::Median, Strategy::Best];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Current => "current",
+            Strategy::Median => "median",
+            Strategy::Best => "best",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|strategy| strategy.name() == name)
+    }
+
+    /// Returns the suggested buy and sell price in refined
+    pub fn suggest(&self, item: &str, listings: &[UniversalListing]) -> (Option<f32>, Option<f32>) {
+        let prices = |intent: &str| -> Vec<f32> {
+            let mut prices: Vec<f32> = listings
+                .iter()
+                .filter(|listing| listing.intent == intent)
+                .map(|listing| listing.price)
+                .collect();
+            prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            prices
+        };
+
+        match self {
+            Strategy::Current => {
+                let suggestion = PriceSuggestion::from_listings(item, listings);
+                (suggestion.buy, suggestion.sell)
+            }
+            Strategy::Median => {
+                let median = |prices: Vec<f32>| prices.get(prices.len() / 2).copied();
+                (median(prices("buy")), median(prices("sell")))
+            }
+            Strategy::Best => (
+                prices("buy").last().copied(),
+                prices("sell").first().copied(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestOptions {
+    pub paths: Vec<std::path::PathBuf>,
+    pub items: Vec<String>,
+    pub strategies: Vec<Strategy>,
+    /// Unix timestamps of the window the strategies are evaluated in, the records before
+    /// `from` only build up the book
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Seconds between two evaluations
+    pub step: i64,
+    /// Seconds after an evaluation the suggestion is checked against the book
+    pub horizon: i64,
+}
+
+/// How one strategy did over the whole window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StrategyReport {
+    pub strategy: String,
+    pub suggestions: usize,
+    /// Suggestions that could be checked, the book had a sell listing after the horizon
+    pub checked: usize,
+    /// Sell suggestions above the lowest sell listing after the horizon
+    pub undercut: usize,
+    /// Buy suggestions at or above the lowest sell listing after the horizon
+    pub overpaid: usize,
+    pub undercut_rate: f32,
+    pub overpaid_rate: f32,
+    /// Number of times a suggestion differed from the previous one of the same item
+    pub changes: usize,
+    /// Average change between consecutive suggestions of an item in percent
+    pub mean_change: f32,
+}
+
+/// Listings of the tracked items as they were at some point in time, kept by the same rules
+/// as the redis store
+#[derive(Debug, Default)]
+struct Book {
+    listings: ListingBook,
+}
+
+impl Book {
+    /// Applies a snapshot the way `update_listings_from_snapshot` does
+    fn apply_snapshot(
+        &mut self,
+        item: &str,
+        snapshot: ListingResponse,
+        filter: &ListingFilter,
+        now: i64,
+    ) {
+        let listings = snapshot
+            .listings
+            .into_iter()
+            .filter(|listing| {
+                listing.user_agent.is_some() && filter.check(&listing.steamid).is_none()
+            })
+            .map(|mut listing| {
+                listing.item.name = Some(item.to_owned());
+                UniversalListing::from(listing)
+            })
+            .collect();
+
+        self.listings
+            .apply_snapshot(item, listings, snapshot.created_at, now);
+    }
+
+    /// Applies the events of a websocket frame the way `ingest_frame` does
+    fn apply_frame(
+        &mut self,
+        frame: DecodedFrame,
+        items: &HashSet<String>,
+        filter: &ListingFilter,
+        now: i64,
+    ) {
+        for event in frame.events {
+            match event {
+                Event::ListingUpdate(listing) => {
+                    if !items.contains(&listing.item.name)
+                        || listing.source != Some("userAgent".to_owned())
+                        || filter.check(&listing.steamid).is_some()
+                    {
+                        continue;
+                    }
+
+                    let item = listing.item.name.clone();
+                    self.listings.upsert(&item, listing.into(), now);
+                }
+                Event::ListingDelete(listing) => {
+                    if items.contains(&listing.item.name) {
+                        let key = listing_key(listing.item.defindex, &listing.canonical_id());
+                        self.listings.delete(&listing.item.name, &key, now);
+                    }
+                }
+                Event::Unknown(_) => {}
+            }
+        }
+    }
+}
+
+/// A suggestion waiting for its horizon to pass
+struct PendingCheck {
+    due: i64,
+    strategy: usize,
+    item: String,
+    buy: Option<f32>,
+    sell: Option<f32>,
+}
+
+struct Tracker {
+    report: StrategyReport,
+    last: HashMap<String, (Option<f32>, Option<f32>)>,
+    change_sum: f32,
+    change_count: usize,
+}
+
+impl Tracker {
+    fn record(&mut self, item: &str, buy: Option<f32>, sell: Option<f32>) {
+        self.report.suggestions += 1;
+
+        if let Some((last_buy, last_sell)) = self.last.insert(item.to_owned(), (buy, sell)) {
+            if (last_buy, last_sell) != (buy, sell) {
+                self.report.changes += 1;
+            }
+
+            for (old, new) in [(last_buy, buy), (last_sell, sell)] {
+                if let (Some(old), Some(new)) = (old, new) {
+                    if old > 0.0 {
+                        self.change_sum += ((new - old) / old).abs() * 100.0;
+                        self.change_count += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, check: &PendingCheck, lowest_sell: Option<f32>) {
+        let Some(lowest_sell) = lowest_sell else {
+            return;
+        };
+
+        self.report.checked += 1;
+        if check.sell.is_some_and(|sell| sell > lowest_sell) {
+            self.report.undercut += 1;
+        }
+        if check.buy.is_some_and(|buy| buy >= lowest_sell) {
+            self.report.overpaid += 1;
+        }
+    }
+
+    fn finish(mut self) -> StrategyReport {
+        if self.report.checked > 0 {
+            self.report.undercut_rate = self.report.undercut as f32 / self.report.checked as f32;
+            self.report.overpaid_rate = self.report.overpaid as f32 / self.report.checked as f32;
+        }
+        if self.change_count > 0 {
+            self.report.mean_change = self.change_sum / self.change_count as f32;
+        }
+
+        self.report
+    }
+}
+
+/// Rebuilds the book from the archive and evaluates the strategies every `step` seconds of
+/// the window, every suggestion is checked against the book `horizon` seconds later
+///
+/// The book follows the rules of the live store with the record time as the clock
+pub fn backtest(options: &BacktestOptions, filter: &ListingFilter) -> Vec<StrategyReport> {
+    let items: HashSet<String> = options.items.iter().cloned().collect();
+    let mut book = Book::default();
+    let mut trackers: Vec<Tracker> = options
+        .strategies
+        .iter()
+        .map(|strategy| Tracker {
+            report: StrategyReport {
+                strategy: strategy.name().to_owned(),
+                ..Default::default()
+            },
+            last: HashMap::new(),
+            change_sum: 0.0,
+            change_count: 0,
+        })
+        .collect();
+
+    let mut pending: VecDeque<PendingCheck> = VecDeque::new();
+    let mut next_step = options.from;
+    let to = options.to.unwrap_or(i64::MAX);
+
+    let records = expand_archive_paths(&options.paths)
+        .into_iter()
+        .filter_map(|path| match ArchiveReader::open(&path) {
+            Ok(reader) => Some(reader),
+            Err(e) => {
+                warn!("Failed to open archive file {:?}: {:?}", path, e);
+                None
+            }
+        })
+        .flatten();
+
+    for record in records {
+        let time = record.received_at() / 1000;
+        if time > to.saturating_add(options.horizon) {
+            break;
+        }
+
+        let step = next_step.get_or_insert(time);
+        while *step <= time && *step <= to {
+            book.listings.remove_expired(*step);
+
+            for item in &options.items {
+                let listings = book.listings.listings(item);
+                for (index, strategy) in options.strategies.iter().enumerate() {
+                    let (buy, sell) = strategy.suggest(item, &listings);
+                    if buy.is_none() && sell.is_none() {
+                        continue;
+                    }
+
+                    trackers[index].record(item, buy, sell);
+                    pending.push_back(PendingCheck {
+                        due: *step + options.horizon,
+                        strategy: index,
+                        item: item.clone(),
+                        buy,
+                        sell,
+                    });
+                }
+            }
+
+            *step += options.step;
+        }
+
+        while pending.front().is_some_and(|check| check.due <= time) {
+            let check = pending.pop_front().unwrap();
+            trackers[check.strategy].check(&check, book.listings.lowest_sell(&check.item));
+        }
+
+        match record {
+            ArchiveRecord::Websocket { frame, .. } => {
+                book.apply_frame(DecodedFrame::decode(&frame), &items, filter, time);
+            }
+            ArchiveRecord::Snapshot { item, body, .. } => {
+                match serde_json::from_str::<ListingResponse>(&body) {
+                    Ok(snapshot) => book.apply_snapshot(&item, snapshot, filter, time),
+                    Err(e) => warn!("Skipping invalid snapshot of item {}: {:?}", item, e),
+                }
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        info!(
+            "{} suggestions couldn't be checked, the archive ends before their horizon",
+            pending.len()
+        );
+    }
+
+    trackers.into_iter().map(Tracker::finish).collect()
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::event::UniversalListing;
+
+/// How long a deleted listing can't be brought back by an older update, in seconds
+pub const TOMBSTONE_TTL: i64 = 3600;
+
+/// Listings that weren't bumped for this long are removed, in seconds
+pub const LISTING_MAX_AGE: i64 = 86400;
+
+/// Outcome of a conditional listing write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpsertResult {
+    Created,
+    Updated,
+    Unchanged,
+    /// The listing was deleted after this version of it was bumped
+    Deleted,
+    /// The stored listing was bumped after this version of it
+    Outdated,
+}
+
+impl From<i64> for UpsertResult {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => UpsertResult::Created,
+            2 => UpsertResult::Updated,
+            -1 => UpsertResult::Deleted,
+            -2 => UpsertResult::Outdated,
+            _ => UpsertResult::Unchanged,
+        }
+    }
+}
+
+/// Decides whether an update of a listing gets written, last writer wins by `bumped_at`
+/// and a deletion wins over every update bumped before it
+///
+/// The redis store makes the same decision in its upsert script
+pub fn upsert_result(
+    stored: Option<&UniversalListing>,
+    deleted_at: Option<i64>,
+    listing: &UniversalListing,
+) -> UpsertResult {
+    if deleted_at.is_some_and(|deleted_at| deleted_at >= listing.bumped_at as i64) {
+        return UpsertResult::Deleted;
+    }
+
+    let Some(stored) = stored else {
+        return UpsertResult::Created;
+    };
+
+    if serde_json::to_string(stored).ok() == serde_json::to_string(listing).ok() {
+        UpsertResult::Unchanged
+    } else if stored.bumped_at > listing.bumped_at {
+        UpsertResult::Outdated
+    } else {
+        UpsertResult::Updated
+    }
+}
+
+/// Whether a stored listing that isn't part of a snapshot anymore gets removed, listings
+/// bumped after the snapshot was created survive it
+pub fn is_vanished(stored: &UniversalListing, created_at: u32) -> bool {
+    stored.bumped_at <= created_at
+}
+
+/// Whether the listing wasn't bumped within `max_age` seconds before `now`
+pub fn is_expired(listing: &UniversalListing, max_age: i64, now: i64) -> bool {
+    (listing.bumped_at as i64) < now - max_age
+}
+
+/// The listings of some items kept in memory by the same rules as the redis store
+///
+/// All the times are unix timestamps passed in by the caller, so archived data can be
+/// applied with the time it was received at
+#[derive(Debug, Default)]
+pub struct ListingBook {
+    items: HashMap<String, HashMap<String, UniversalListing>>,
+    /// Deletion time by listing key
+    tombstones: HashMap<String, i64>,
+}
+
+impl ListingBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn deleted_at(&self, key: &str, now: i64) -> Option<i64> {
+        self.tombstones
+            .get(key)
+            .copied()
+            .filter(|deleted_at| now - deleted_at < TOMBSTONE_TTL)
+    }
+
+    /// Writes the listing unless it was deleted or the stored one is newer
+    pub fn upsert(&mut self, item: &str, listing: UniversalListing, now: i64) -> UpsertResult {
+        let key = listing.key();
+        let deleted_at = self.deleted_at(&key, now);
+        let listings = self.items.entry(item.to_owned()).or_default();
+
+        let result = upsert_result(listings.get(&key), deleted_at, &listing);
+        if matches!(result, UpsertResult::Created | UpsertResult::Updated) {
+            listings.insert(key, listing);
+        }
+
+        result
+    }
+
+    /// Removes the listing and leaves a tombstone, returns whether it was stored
+    pub fn delete(&mut self, item: &str, key: &str, now: i64) -> bool {
+        self.tombstones.insert(key.to_owned(), now);
+
+        self.items
+            .get_mut(item)
+            .is_some_and(|listings| listings.remove(key).is_some())
+    }
+
+    /// Applies a snapshot of the item the way `Database::update_listings_from_snapshot` does,
+    /// returns the number of vanished listings that got removed
+    pub fn apply_snapshot(
+        &mut self,
+        item: &str,
+        listings: Vec<UniversalListing>,
+        created_at: u32,
+        now: i64,
+    ) -> usize {
+        let seen: HashSet<String> = listings.iter().map(|listing| listing.key()).collect();
+        for listing in listings {
+            self.upsert(item, listing, now);
+        }
+
+        let Some(stored) = self.items.get_mut(item) else {
+            return 0;
+        };
+
+        let before = stored.len();
+        stored.retain(|key, listing| seen.contains(key) || !is_vanished(listing, created_at));
+        before - stored.len()
+    }
+
+    /// Removes the listings that weren't bumped within `LISTING_MAX_AGE` before `now` and
+    /// the expired tombstones, returns the number of removed listings
+    pub fn remove_expired(&mut self, now: i64) -> usize {
+        self.tombstones
+            .retain(|_, deleted_at| now - *deleted_at < TOMBSTONE_TTL);
+
+        let mut removed = 0;
+        for listings in self.items.values_mut() {
+            let before = listings.len();
+            listings.retain(|_, listing| !is_expired(listing, LISTING_MAX_AGE, now));
+            removed += before - listings.len();
+        }
+
+        removed
+    }
+
+    pub fn listings(&self, item: &str) -> Vec<UniversalListing> {
+        self.items
+            .get(item)
+            .map(|listings| listings.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Price of the cheapest sell listing of the item
+    pub fn lowest_sell(&self, item: &str) -> Option<f32> {
+        self.items
+            .get(item)?
+            .values()
+            .filter(|listing| listing.intent == "sell")
+            .map(|listing| listing.price)
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+    }
+}
@@ -1,85 +1,322 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use chrono::Utc;
 use futures_util::StreamExt;
-use log::{debug, error};
-use reqwest::Client;
-use tokio::sync::Mutex;
+use log::{debug, error, warn};
+use redis::RedisError;
+use reqwest::{header::RETRY_AFTER, Client, StatusCode};
+use tokio::sync::mpsc;
 use tokio_tungstenite::connect_async;
 
+#[cfg(feature = "archive")]
+use crate::archive::Recorder;
 use crate::{
-    db::Database,
-    event::{Event, EventListing, EventListingDeletion},
+    cluster::Cluster,
+    db::{Database, SnapshotSync},
+    event::{DecodedFrame, Event, EventListing, EventListingDeletion},
+    notifier::Notifier,
+    ratelimit::RateLimiter,
     types::{ListingResponse, PricingError},
 };
 
-const BASE_URL: &str = "https://backpack.tf/api";
-const WS_URL: &str = "wss://ws.backpack.tf/events";
+const DEFAULT_BASE_URL: &str = "https://backpack.tf/api";
+const DEFAULT_WS_URL: &str = "wss://ws.backpack.tf/events";
+
+/// Default number of websocket frames that can wait for the database before reading pauses
+const DEFAULT_WS_QUEUE_SIZE: usize = 256;
+
+/// Default number of snapshots that are fetched at the same time
+const DEFAULT_SNAPSHOT_CONCURRENCY: usize = 4;
+
+/// Default minimum time between two snapshot requests, in milliseconds
+const DEFAULT_SNAPSHOT_INTERVAL_MS: u64 = 500;
 
+/// How long snapshot requests pause after being rate limited without a `Retry-After` header
+const RATE_LIMIT_PAUSE: Duration = Duration::from_secs(60);
+
+/// Requests snapshots from backpack.tf, without storing anything
+///
+/// Clones share the rate limiter, so requests are spaced out across all of them
 #[derive(Clone)]
-pub struct BackpackTF {
+pub struct SnapshotClient {
     req_client: Client,
-    auth_key: String,
     user_token: String,
-    db: Arc<Mutex<Database>>,
-    // TODO: perf: look for faster hashmap implementation
-    snapshot_cache: HashMap<String, u32>,
+    /// Root of the backpack.tf API, e.g. `https://backpack.tf/api`
+    base_url: String,
+    limiter: RateLimiter,
+}
+
+impl SnapshotClient {
+    pub fn new(
+        user_token: String,
+        base_url: String,
+        interval: Duration,
+    ) -> Result<Self, reqwest::Error> {
+        let client = reqwest::ClientBuilder::new().build()?;
+
+        Ok(Self {
+            req_client: client,
+            user_token,
+            base_url,
+            limiter: RateLimiter::new(interval),
+        })
+    }
+
+    /// Requests a snapshot of the given item, returns it together with the raw body
+    ///
+    /// A rate limited response pauses all requests for its `Retry-After`
+    pub async fn fetch(&self, item: &str) -> Result<(ListingResponse, String), PricingError> {
+        self.limiter.acquire().await;
+
+        let req = match self
+            .req_client
+            .get(format!("{}/classifieds/listings/snapshot", self.base_url))
+            .query(&[
+                ("token", &self.user_token),
+                ("appid", &"440".to_owned()),
+                ("sku", &item.to_owned()),
+            ])
+            .send()
+            .await
+        {
+            Ok(req) => req,
+            Err(e) => {
+                error!("Failed to send request to Backpack.tf: {:?}", e);
+                return Err(PricingError::InternalError);
+            }
+        };
+
+        if req.status() == StatusCode::TOO_MANY_REQUESTS {
+            let pause = req
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(RATE_LIMIT_PAUSE);
+
+            warn!(
+                "Rate limited by Backpack.tf, pausing snapshot requests for {:?}",
+                pause
+            );
+            self.limiter.pause(pause).await;
+            return Err(PricingError::RateLimited);
+        }
+
+        if req.status().is_server_error() {
+            return Err(PricingError::ServerError);
+        }
+
+        if req.status() != 200 {
+            error!(
+                "Failed to get price from Backpack.tf: {:?}, {:?}",
+                req.status(),
+                req.text().await.unwrap()
+            );
+            return Err(PricingError::InternalError);
+        }
+
+        let body = match req.text().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to read response from Backpack.tf: {:?}", e);
+                return Err(PricingError::InternalError);
+            }
+        };
+
+        match serde_json::from_str(&body) {
+            Ok(res) => Ok((res, body)),
+            Err(e) => {
+                error!("Failed to parse JSON from request: {:?}", e);
+                Err(PricingError::InternalError)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BackpackTF {
+    auth_key: String,
+    db: Database,
+    snapshots: SnapshotClient,
+    ws_url: String,
+    /// Capacity of the queue between reading the websocket and writing to the database
+    ws_queue_size: usize,
+    /// Number of snapshots that are fetched at the same time
+    snapshot_concurrency: usize,
+    notifier: Option<Notifier>,
+    cluster: Option<Cluster>,
+    #[cfg(feature = "archive")]
+    recorder: Option<Recorder>,
 }
 
 impl BackpackTF {
     // TODO: impl Default instead
     pub fn new(auth_key: String, user_token: String, database: Database) -> Result<Self, ()> {
-        let Ok(client) = reqwest::ClientBuilder::new().build() else {
+        let base_url =
+            std::env::var("BPTF_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_owned());
+        let ws_url = std::env::var("BPTF_WS_URL").unwrap_or_else(|_| DEFAULT_WS_URL.to_owned());
+
+        let ws_queue_size = match std::env::var("WS_QUEUE_SIZE") {
+            Ok(size) => size.parse().expect("WS_QUEUE_SIZE is not a number"),
+            Err(_) => DEFAULT_WS_QUEUE_SIZE,
+        };
+
+        let snapshot_concurrency = match std::env::var("SNAPSHOT_CONCURRENCY") {
+            Ok(concurrency) => concurrency
+                .parse()
+                .expect("SNAPSHOT_CONCURRENCY is not a number"),
+            Err(_) => DEFAULT_SNAPSHOT_CONCURRENCY,
+        };
+
+        let snapshot_interval = match std::env::var("SNAPSHOT_INTERVAL_MS") {
+            Ok(interval) => interval
+                .parse()
+                .expect("SNAPSHOT_INTERVAL_MS is not a number"),
+            Err(_) => DEFAULT_SNAPSHOT_INTERVAL_MS,
+        };
+
+        let Ok(snapshots) = SnapshotClient::new(
+            user_token,
+            base_url,
+            Duration::from_millis(snapshot_interval),
+        ) else {
             return Err(());
         };
 
         Ok(Self {
-            req_client: client,
-            user_token,
             auth_key,
-            db: Arc::new(Mutex::new(database)),
-            snapshot_cache: HashMap::new(),
+            db: database,
+            snapshots,
+            ws_url,
+            ws_queue_size,
+            snapshot_concurrency: snapshot_concurrency.max(1),
+            notifier: None,
+            cluster: None,
+            #[cfg(feature = "archive")]
+            recorder: None,
         })
     }
 
-    pub async fn watch_snapshots(&mut self, items: Vec<String>) {
-        for item in items {
-            let snapshot = self.get_snapshot(&item).await;
+    /// Points the client at another backpack.tf, e.g. a mock server in tests
+    pub fn set_endpoints(&mut self, base_url: String, ws_url: String) {
+        self.snapshots.base_url = base_url;
+        self.ws_url = ws_url;
+    }
+
+    /// Sets the notifier that gets told about the items changed by snapshots and websocket events
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
 
-            if let Err(e) = snapshot {
-                if e == PricingError::IsAlreadyCached {
-                    debug!("Snapshot for item {} is already cached", item);
-                    continue;
-                }
+    /// Sets the cluster that decides which items this instance refreshes
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.cluster = Some(cluster);
+    }
+
+    /// Sets the recorder that archives the raw websocket frames and snapshot responses
+    #[cfg(feature = "archive")]
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Fetches and stores the snapshots of all the items, `SNAPSHOT_CONCURRENCY` at a time
+    ///
+    /// Every snapshot is written as soon as it arrives, so a slow or failing item doesn't
+    /// hold up the others
+    pub async fn watch_snapshots(&self, items: Vec<String>) {
+        futures_util::stream::iter(items)
+            .for_each_concurrent(self.snapshot_concurrency, |item| async move {
+                self.sync_snapshot(&item).await;
+            })
+            .await;
+    }
+
+    /// Fetches the snapshot of one item and writes it to the database
+    async fn sync_snapshot(&self, item: &str) {
+        if let Some(cluster) = &self.cluster {
+            if !cluster.claim_item(item).await {
+                debug!("Snapshot of item {} is owned by another instance", item);
+                return;
+            }
+        }
+
+        let snapshot = match self.get_snapshot(item).await {
+            Ok(snapshot) => snapshot,
+            Err(PricingError::IsAlreadyCached) => {
+                debug!("Snapshot for item {} is already cached", item);
+                return;
+            }
+            Err(e) => {
                 error!("Failed to get snapshot for item {}: {:?}", item, e);
-                continue;
+                return;
             }
+        };
+
+        if let Err(e) = self.store_snapshot(item, snapshot).await {
+            error!("Failed to store listings in the database: {:?}", e);
+        }
+    }
 
-            let listings = snapshot.unwrap().listings;
-            let listings_len = listings.len();
+    /// Writes the snapshot of the item to the database and tells the notifier if anything changed
+    pub async fn store_snapshot(
+        &self,
+        item: &str,
+        snapshot: ListingResponse,
+    ) -> Result<SnapshotSync, RedisError> {
+        let sync = self
+            .db
+            .update_listings_from_snapshot(snapshot.listings, item, snapshot.created_at)
+            .await?;
+
+        if sync.has_changes() {
+            if let Some(notifier) = &self.notifier {
+                notifier.check_items(HashSet::from([item.to_owned()])).await;
+            }
+        }
 
-            match self
-                .db
-                .lock()
-                .await
-                .update_listings_from_snapshot(listings, &item)
-                .await
-            {
-                Ok(_) => {
-                    //    info!("Stored {} listings for item {}", listings_len, item);
+        Ok(sync)
+    }
+
+    /// Keeps the Backpack.tf websocket open, reconnects `reconnect_delay` after it closed
+    ///
+    /// With a cluster only the leader reads the websocket, the others wait to take over
+    pub async fn keep_websocket(&self, items: Vec<String>, reconnect_delay: Duration) {
+        loop {
+            match &self.cluster {
+                Some(cluster) => {
+                    cluster.wait_for_leadership().await;
+
+                    tokio::select! {
+                        _ = self.watch_websocket(items.clone()) => {
+                            warn!("Backpack.tf websocket closed, reconnecting");
+                        }
+                        _ = cluster.wait_for_lost_leadership() => {
+                            warn!("Lost the leadership, closing the Backpack.tf websocket");
+                        }
+                    }
                 }
-                Err(e) => {
-                    error!("Failed to store listings in the database: {:?}", e);
+                None => {
+                    self.watch_websocket(items.clone()).await;
+                    warn!("Backpack.tf websocket closed, reconnecting");
                 }
             }
+
+            tokio::time::sleep(reconnect_delay).await;
         }
     }
 
     /// Reads the stream of events from the Backpack.tf websocket in a loop
     ///
+    /// Frames are decoded while reading and handed to a separate writer through a bounded
+    /// queue, reading pauses while the queue is full so the database sets the pace
+    ///
     /// This is used later to update the price on demand
     pub async fn watch_websocket(&self, items: Vec<String>) {
-        let (ws_stream, _) = match connect_async(WS_URL).await {
+        let (ws_stream, _) = match connect_async(&self.ws_url).await {
             Ok(stream) => stream,
             Err(e) => {
                 error!("Failed to connect to Backpack.tf websocket: {:?}", e);
@@ -87,8 +324,15 @@ impl BackpackTF {
             }
         };
 
+        let (sender, receiver) = mpsc::channel(self.ws_queue_size);
+        let writer = self.clone();
+        let write_task = tokio::spawn(async move {
+            writer.write_frames(receiver, items).await;
+        });
+
+        let queue = &sender;
         let (_, read) = ws_stream.split();
-        read.for_each(|msg| async {
+        read.for_each(|msg| async move {
             let msg = match msg {
                 Ok(msg) => msg,
                 Err(e) => {
@@ -112,131 +356,180 @@ impl BackpackTF {
                 return;
             }
 
-            let events: Vec<Event> = match serde_json::from_str(msg) {
-                Ok(events) => events,
-                Err(e) => {
-                    error!("Failed to deserialize events: {:?}", e);
-                    return;
-                }
-            };
-
-            let listings: Vec<EventListing> = events
-                .clone()
-                .into_iter()
-                .filter_map(|event| match event {
-                    Event::ListingUpdate(listing) => {
-                        if items.contains(&listing.item.name)
-                            && listing.source == Some("userAgent".to_owned())
-                        {
-                            Some(listing)
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                })
-                .collect();
-
-            let listings_deleted: Vec<EventListingDeletion> = events
-                .into_iter()
-                .filter_map(|event| match event {
-                    Event::ListingDelete(listing) => {
-                        // dont check for source here since its possible that the listing was deleted by the user
-                        if items.contains(&listing.item.name) {
-                            Some(listing)
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                })
-                .collect();
-
-            self.db
-                .lock()
-                .await
-                .update_listings_from_websocket(listings.clone())
-                .await
-                .unwrap();
-
-            self.db
-                .lock()
-                .await
-                .handle_delete_events(listings_deleted)
-                .await;
-            //self.db.handle_delete_events(listings_deleted).await;
-            /*self.db
-            .update_listings_from_websocket(listings.clone())
-            .await
-            .unwrap();*/
-
-            /*    let listings: Vec<EventListing> = events
-                .into_iter()
-                .filter_map(|event| match event {
-                    Event::ListingUpdate(listing) => Some(listing),
-                    _ => None,
-                })
-                .collect();
+            #[cfg(feature = "archive")]
+            if let Some(recorder) = &self.recorder {
+                recorder.record_frame(msg).await;
+            }
 
-            self.db.store_listings(listings.clone()).await;
+            if queue.capacity() == 0 {
+                warn!("Websocket write queue is full, waiting for the database");
+            }
 
-            info!("Stored {} listings", listings.len());   */
+            if queue.send(DecodedFrame::decode(msg)).await.is_err() {
+                error!("Websocket writer stopped, dropping frame");
+            }
         })
         .await;
+
+        drop(sender);
+        if let Err(e) = write_task.await {
+            error!("Websocket writer failed: {:?}", e);
+        }
+    }
+
+    /// Writes the decoded websocket frames to the database until the reader goes away
+    async fn write_frames(&self, mut receiver: mpsc::Receiver<DecodedFrame>, items: Vec<String>) {
+        while let Some(frame) = receiver.recv().await {
+            self.write_frame(frame, &items).await;
+        }
+    }
+
+    async fn write_frame(&self, frame: DecodedFrame, items: &[String]) {
+        let changed_items = ingest_frame(&self.db, frame, items, Utc::now().timestamp()).await;
+
+        if let Some(notifier) = &self.notifier {
+            if !changed_items.is_empty() {
+                notifier.check_items(changed_items).await;
+            }
+        }
     }
 
     /// Requests a snapshot of the given item from Backpack.tf
     /// This is used to get the current listings for the item.
     /// The original item name is used to get the listings.
-    pub async fn get_snapshot(&mut self, item: &str) -> Result<ListingResponse, PricingError> {
-        let key = self.snapshot_cache.get(item);
+    /// Snapshots fetched less than a minute ago by any instance are skipped
+    pub async fn get_snapshot(&self, item: &str) -> Result<ListingResponse, PricingError> {
+        // the fetch time lives in redis so every clone and process shares it
+        let freshness = match self.db.get_snapshot_freshness(item).await {
+            Ok(freshness) => freshness,
+            Err(e) => {
+                error!("Failed to get snapshot freshness of item {}: {:?}", item, e);
+                return Err(PricingError::InternalError);
+            }
+        };
 
-        if let Some(timestamp) = key {
-            if Utc::now().timestamp() as u32 - timestamp < 60 {
+        if let Some(fetched_at) = freshness.fetched_at {
+            if Utc::now().timestamp() - fetched_at < 60 {
                 return Err(PricingError::IsAlreadyCached);
             }
         }
 
-        let req = match self
-            .req_client
-            .get(format!("{}/classifieds/listings/snapshot", BASE_URL))
-            .query(&[
-                ("token", &self.user_token),
-                ("appid", &"440".to_owned()),
-                ("sku", &item.to_owned()),
-            ])
-            .send()
+        self.fetch_snapshot(item).await
+    }
+
+    /// Requests a snapshot of the given item from Backpack.tf no matter how fresh the last one is
+    ///
+    /// Requests are spaced out by the snapshot rate limiter, the fetch and creation time of
+    /// the snapshot are stored
+    pub async fn fetch_snapshot(&self, item: &str) -> Result<ListingResponse, PricingError> {
+        let (res, body) = self.snapshots.fetch(item).await?;
+
+        if let Err(e) = self
+            .db
+            .set_snapshot_fetched_at(item, Utc::now().timestamp())
             .await
         {
-            Ok(req) => req,
-            Err(e) => {
-                error!("Failed to send request to Backpack.tf: {:?}", e);
-                return Err(PricingError::InternalError);
-            }
-        };
+            error!(
+                "Failed to store snapshot fetch time of item {}: {:?}",
+                item, e
+            );
+        }
 
-        if req.status().is_server_error() {
-            return Err(PricingError::ServerError);
+        #[cfg(feature = "archive")]
+        if let Some(recorder) = &self.recorder {
+            recorder.record_snapshot(item, &body).await;
         }
+        #[cfg(not(feature = "archive"))]
+        let _ = body;
 
-        if req.status() != 200 {
+        if let Err(e) = self.db.set_snapshot_created_at(item, res.created_at).await {
             error!(
-                "Failed to get price from Backpack.tf: {:?}, {:?}",
-                req.status(),
-                req.text().await.unwrap()
+                "Failed to store snapshot creation time of item {}: {:?}",
+                item, e
             );
-            return Err(PricingError::InternalError);
         }
 
-        self.snapshot_cache
-            .insert(item.to_owned(), Utc::now().timestamp() as u32);
+        Ok(res)
+    }
+}
 
-        match req.json().await {
-            Ok(res) => Ok(res),
-            Err(e) => {
-                error!("Failed to parse JSON from request: {:?}", e);
-                Err(PricingError::InternalError)
+/// Writes the events of one websocket frame to the database, only listings of the given
+/// items are kept
+///
+/// `received_at` is the unix timestamp the frame was received at, deleted listings are
+/// tombstoned with it. Returns the names of the items whose listings changed
+pub async fn ingest_frame(
+    db: &Database,
+    frame: DecodedFrame,
+    items: &[String],
+    received_at: i64,
+) -> HashSet<String> {
+    let DecodedFrame {
+        events,
+        dead_letters,
+    } = frame;
+
+    let mut event_counts: HashMap<String, u64> = HashMap::new();
+    for event in &events {
+        *event_counts
+            .entry(event.event_type().to_owned())
+            .or_default() += 1;
+    }
+
+    if !dead_letters.is_empty() {
+        warn!(
+            "Failed to decode {} events, storing them as dead letters",
+            dead_letters.len()
+        );
+        event_counts.insert("invalid".to_owned(), dead_letters.len() as u64);
+    }
+
+    if let Err(e) = db.record_event_counts(&event_counts).await {
+        error!("Failed to record event counts: {:?}", e);
+    }
+
+    if let Err(e) = db.push_event_dead_letters(&dead_letters).await {
+        error!("Failed to store event dead letters: {:?}", e);
+    }
+
+    let mut listings: Vec<EventListing> = Vec::new();
+    let mut listings_deleted: Vec<EventListingDeletion> = Vec::new();
+    for event in events {
+        match event {
+            Event::ListingUpdate(listing) => {
+                if items.contains(&listing.item.name)
+                    && listing.source == Some("userAgent".to_owned())
+                {
+                    listings.push(listing);
+                }
+            }
+            Event::ListingDelete(listing) => {
+                // dont check for source here since its possible that the listing was deleted by the user
+                if items.contains(&listing.item.name) {
+                    listings_deleted.push(listing);
+                }
             }
+            Event::Unknown(_) => {}
         }
     }
+
+    let changed_items: HashSet<String> = listings
+        .iter()
+        .map(|listing| listing.item.name.clone())
+        .chain(
+            listings_deleted
+                .iter()
+                .map(|listing| listing.item.name.clone()),
+        )
+        .collect();
+
+    if let Err(e) = db.update_listings_from_websocket(listings).await {
+        error!("Failed to store websocket listings: {:?}", e);
+    }
+
+    if let Err(e) = db.handle_delete_events(listings_deleted, received_at).await {
+        error!("Failed to delete websocket listings: {:?}", e);
+    }
+
+    changed_items
 }
@@ -0,0 +1,1515 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use redis::RedisError;
+use serde_json::json;
+use tf2_price::{Currencies, Rounding};
+
+use crate::{
+    api::{self, AppState},
+    archive::Recorder,
+    backtest::{backtest, BacktestOptions, Strategy},
+    book::LISTING_MAX_AGE,
+    bptf::BackpackTF,
+    cluster::Cluster,
+    db::Database,
+    export::{listing_rows, price_rows, select_items, write_rows, ExportError, ExportFormat},
+    feed::Feed,
+    filter::ListingFilter,
+    guard::{BreakerReason, BreakerTrip, CircuitBreaker},
+    items::{parse_tracked_items, TrackedItem},
+    margin::MarginStatus,
+    notifier::Notifier,
+    pricelist::{build_pricelist, import_pricelist, parse_pricelist},
+    pricing::{
+        get_item_prices, get_key_price, get_suggestion, ItemCurrencies, ItemPrice, PriceConfig,
+        PriceOverride, KEY_ITEM,
+    },
+    replay::{replay, ReplayOptions},
+    types::{parse_time, PricingError},
+};
+
+/// Exit code of commands that failed while running, e.g. because redis is unreachable
+const EXIT_FAILURE: u8 = 1;
+/// Exit code of invalid command lines
+const EXIT_USAGE: u8 = 2;
+/// Exit code of commands about an item that isn't tracked or has no price
+const EXIT_NOT_FOUND: u8 = 3;
+
+const USAGE: &str = "\
+Usage: pricer [command] [options]
+
+Commands:
+  sync                   Sync the listings of the tracked items and publish prices (default)
+  serve                  Serve the API without syncing
+  price <sku>            Print the price suggestion of a tracked item
+  listings <sku>         Print the stored listings of a tracked item
+  snapshot <sku>         Fetch and store the snapshot of a tracked item once
+  prune                  Remove the listings that weren't bumped for a while
+  export                 Write the prices of all the tracked items
+  pricelist <action>     Import or export a tf2autobot pricelist.json
+  override <action>      List, set or remove manual price overrides
+  review <action>        List, approve or reject the prices held by the price guard
+  breaker <action>       Show, trip or reset the circuit breaker
+  replay <archive>...    Feed archived events into a scratch redis
+  backtest <archive>...  Score pricing strategies on archived listings
+  help                   Print this message
+
+Run `pricer <command> --help` for the options of a command.
+
+Exit codes: 0 on success, 1 if the command failed, 2 on invalid usage,
+3 if the item isn't tracked or has no price
+";
+
+const SYNC_USAGE: &str = "\
+Usage: pricer sync [options]
+
+Syncs the listings of the tracked items from backpack.tf and publishes their prices
+
+Options:
+  --items <items>      Items to track, overrides ITEMS
+  --api <address>      Address to serve the API on, overrides API_ADDRESS
+  --no-api             Don't serve the API even if API_ADDRESS is set
+  --redis <url>        Redis to sync into, overrides REDIS_URL
+";
+
+const SERVE_USAGE: &str = "\
+Usage: pricer serve [options]
+
+Serves the API from the data another instance syncs
+
+Options:
+  --address <address>  Address to serve the API on, overrides API_ADDRESS
+  --redis <url>        Redis to read from, overrides REDIS_URL
+";
+
+const PRICE_USAGE: &str = "\
+Usage: pricer price <sku> [options]
+
+Prints the current price suggestion of a tracked item, by sku or name, and its
+override if it has one
+
+Options:
+  --json               Print the suggestion as JSON
+  --redis <url>        Redis to read from, overrides REDIS_URL
+";
+
+const LISTINGS_USAGE: &str = "\
+Usage: pricer listings <sku> [options]
+
+Prints the stored listings of a tracked item, by sku or name, best prices first
+
+Options:
+  --intent <intent>    Only print the buy or sell listings
+  --json               Print the listings as JSON
+  --redis <url>        Redis to read from, overrides REDIS_URL
+";
+
+const SNAPSHOT_USAGE: &str = "\
+Usage: pricer snapshot <sku> [options]
+
+Fetches the snapshot of a tracked item from backpack.tf and stores it
+
+Options:
+  --force              Fetch even if the last snapshot is less than a minute old
+  --json               Print what the snapshot changed as JSON
+  --redis <url>        Redis to write to, overrides REDIS_URL
+";
+
+const PRUNE_USAGE: &str = "\
+Usage: pricer prune [options]
+
+Removes the listings that weren't bumped for a while
+
+Options:
+  --max-age <hours>    Age of the listings that get removed, defaults to 24
+  --redis <url>        Redis to prune, overrides REDIS_URL
+";
+
+const EXPORT_USAGE: &str = "\
+Usage: pricer export [items|listings|prices] [options]
+
+Writes the current prices of the tracked items in the format of `GET /items` (items, the
+default), their stored listings or the history of their published prices
+
+Options for listings and prices:
+  --format <format>    csv, jsonl or parquet, defaults to csv
+  --skus <skus>        Comma separated skus or names, defaults to all the tracked items
+  --from <time>        Only what was bumped or published since, unix timestamp or RFC 3339
+  --to <time>          Only what was bumped or published until, unix timestamp or RFC 3339
+
+Options:
+  --output <file>      File to write to instead of stdout
+  --redis <url>        Redis to read from, overrides REDIS_URL
+";
+
+const PRICELIST_USAGE: &str = "\
+Usage: pricer pricelist import <file> [options]
+       pricer pricelist export [options]
+
+import tracks the items of a tf2autobot pricelist.json and keeps their settings, entries
+that aren't autopriced become overrides unless the item is overridden by hand. export
+writes a complete pricelist.json of the tracked items from their current prices
+
+Options:
+  --force              Replace the overrides set by hand on import
+  --output <file>      File to export to instead of stdout
+  --redis <url>        Redis to use, overrides REDIS_URL
+";
+
+const OVERRIDE_USAGE: &str = "\
+Usage: pricer override list [options]
+       pricer override set <sku> --buy <price> --sell <price> --reason <reason> [options]
+       pricer override remove <sku> [options]
+
+Overrides pin the price of an item, they are used instead of the suggestion everywhere
+until they expire or get removed. Prices are keys and metal like \"1 key, 10.55 ref\"
+or \"10.55 ref\"
+
+Options:
+  --buy <price>        Buy price of the item
+  --sell <price>       Sell price of the item
+  --reason <reason>    Why the price is pinned
+  --author <name>      Who pinned it, defaults to USER
+  --expires <time>     When the suggestion is used again, unix timestamp or RFC 3339,
+                       never by default
+  --json               Print the overrides as JSON
+  --redis <url>        Redis to use, overrides REDIS_URL
+";
+
+const REVIEW_USAGE: &str = "\
+Usage: pricer review list [options]
+       pricer review approve <sku> [options]
+       pricer review reject <sku> [options]
+
+Prices that moved more than PRICE_GUARD_MAX_CHANGE percent or where buy is above sell
+wait in the review queue. Approving publishes the held price, rejecting drops it
+
+Options:
+  --json               Print the review queue as JSON
+  --redis <url>        Redis to use, overrides REDIS_URL
+";
+
+const BREAKER_USAGE: &str = "\
+Usage: pricer breaker status [options]
+       pricer breaker trip --reason <reason> [options]
+       pricer breaker reset [options]
+
+While the circuit breaker is tripped no price gets published. It trips on its own during
+websocket outages and mass deletions
+
+Options:
+  --reason <reason>    Why the breaker is tripped by hand
+  --json               Print the status as JSON
+  --redis <url>        Redis to use, overrides REDIS_URL
+";
+
+const REPLAY_USAGE: &str = "\
+Usage: pricer replay <archive>... [options]
+
+Feeds archived websocket frames and snapshots through the ingest code of the live sync,
+the arguments are archive files or directories of archive files. Websocket listings are
+kept for the items in ITEMS
+
+Options:
+  --speed <factor>     Replay that many times faster than real time, as fast as possible
+                       by default
+  --redis <url>        Redis to replay into, overrides REPLAY_REDIS_URL. Meant to be a
+                       scratch store, the one in REDIS_URL is refused
+  --allow-live         Replay into the redis in REDIS_URL anyway, to rebuild the live store
+";
+
+const BACKTEST_USAGE: &str = "\
+Usage: pricer backtest <archive>... [options]
+
+Rebuilds the listings of the items in ITEMS from the archive in memory and scores the
+pricing strategies on them, the arguments are archive files or directories of archive files
+
+Options:
+  --from <time>        Start of the evaluated window, unix timestamp or RFC 3339
+  --to <time>          End of the evaluated window, unix timestamp or RFC 3339
+  --step <seconds>     Time between two evaluations, defaults to 300
+  --horizon <seconds>  Time after which a suggestion is checked, defaults to 3600
+  --strategy <name>    current, median or best, can be passed more than once, defaults
+                       to all of them
+  --json               Print the report as JSON
+";
+
+/// Why a command didn't succeed
+#[derive(Debug)]
+enum CommandError {
+    /// `--help` was passed, the usage of the command gets printed
+    Help,
+    /// Invalid command line, the usage of the command gets printed
+    Usage(String),
+    NotFound(String),
+    Failed(String),
+}
+
+impl From<RedisError> for CommandError {
+    fn from(e: RedisError) -> Self {
+        CommandError::Failed(format!("Redis error: {}", e))
+    }
+}
+
+impl From<ExportError> for CommandError {
+    fn from(e: ExportError) -> Self {
+        match e {
+            ExportError::UnknownSku(sku) => {
+                CommandError::NotFound(format!("Item {} is not tracked", sku))
+            }
+            e => CommandError::Failed(format!("Failed to export: {:?}", e)),
+        }
+    }
+}
+
+type CommandResult = Result<(), CommandError>;
+
+/// The parsed options and positional arguments of a command
+struct Args {
+    /// Every value of an option, in the order they were passed
+    values: HashMap<String, Vec<String>>,
+    switches: HashSet<String>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    /// Parses the arguments, `values` are the options that take a value and `switches`
+    /// the ones that don't. Values can be passed as `--name value` or `--name=value`
+    fn parse(args: Vec<String>, values: &[&str], switches: &[&str]) -> Result<Self, CommandError> {
+        let mut parsed = Self {
+            values: HashMap::new(),
+            switches: HashSet::new(),
+            positional: Vec::new(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(CommandError::Help);
+            }
+
+            if !arg.starts_with("--") {
+                parsed.positional.push(arg);
+                continue;
+            }
+
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+
+            if values.contains(&name.as_str()) {
+                let value = match inline_value.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => return Err(CommandError::Usage(format!("{} needs a value", name))),
+                };
+                parsed.values.entry(name).or_default().push(value);
+            } else if switches.contains(&name.as_str()) && inline_value.is_none() {
+                parsed.switches.insert(name);
+            } else {
+                return Err(CommandError::Usage(format!("Unknown option {}", name)));
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// The last value of the option
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .and_then(|values| values.last())
+            .map(|value| value.as_str())
+    }
+
+    /// All the values of an option that can be passed more than once
+    fn all_values(&self, name: &str) -> &[String] {
+        self.values
+            .get(name)
+            .map(|values| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn parsed_value<T: FromStr>(&self, name: &str) -> Result<Option<T>, CommandError> {
+        match self.value(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(CommandError::Usage(format!("{} is not a number", name))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+
+    /// The single positional argument of the commands that take a sku
+    fn sku(&self) -> Result<&str, CommandError> {
+        match self.positional.as_slice() {
+            [sku] => Ok(sku),
+            [] => Err(CommandError::Usage("Missing the sku".to_owned())),
+            [_, unexpected, ..] => Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            ))),
+        }
+    }
+
+    fn no_positional(&self) -> Result<(), CommandError> {
+        match self.positional.first() {
+            Some(unexpected) => Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs the command line without the program name, `sync` if no command is given
+pub async fn run(args: Vec<String>) -> ExitCode {
+    let mut args = args.into_iter();
+    let command = args.next().unwrap_or_else(|| "sync".to_owned());
+    let args: Vec<String> = args.collect();
+
+    let (result, usage) = match command.as_str() {
+        "sync" => (sync(args).await, SYNC_USAGE),
+        "serve" => (serve(args).await, SERVE_USAGE),
+        "price" => (price(args).await, PRICE_USAGE),
+        "listings" => (listings(args).await, LISTINGS_USAGE),
+        "snapshot" => (snapshot(args).await, SNAPSHOT_USAGE),
+        "prune" => (prune(args).await, PRUNE_USAGE),
+        "export" => (export(args).await, EXPORT_USAGE),
+        "pricelist" => (pricelist(args).await, PRICELIST_USAGE),
+        "override" => (price_override(args).await, OVERRIDE_USAGE),
+        "review" => (review(args).await, REVIEW_USAGE),
+        "breaker" => (breaker(args).await, BREAKER_USAGE),
+        "replay" => (replay_archive(args).await, REPLAY_USAGE),
+        "backtest" => (backtest_archive(args), BACKTEST_USAGE),
+        "help" | "--help" | "-h" => (Err(CommandError::Help), USAGE),
+        _ => (
+            Err(CommandError::Usage(format!("Unknown command {}", command))),
+            USAGE,
+        ),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CommandError::Help) => {
+            print!("{}", usage);
+            ExitCode::SUCCESS
+        }
+        Err(CommandError::Usage(message)) => {
+            eprintln!("{}\n\n{}", message, usage);
+            ExitCode::from(EXIT_USAGE)
+        }
+        Err(CommandError::NotFound(message)) => {
+            eprintln!("{}", message);
+            ExitCode::from(EXIT_NOT_FOUND)
+        }
+        Err(CommandError::Failed(message)) => {
+            eprintln!("{}", message);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Connects to the redis at `--redis` or `REDIS_URL`
+async fn connect(args: &Args) -> Result<Database, CommandError> {
+    let url = match args.value("--redis") {
+        Some(url) => url.to_owned(),
+        None => match std::env::var("REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                return Err(CommandError::Usage(
+                    "No redis to connect to, pass --redis or set REDIS_URL".to_owned(),
+                ))
+            }
+        },
+    };
+
+    match Database::try_connect(&url).await {
+        Ok(db) => Ok(db),
+        Err(e) => Err(CommandError::Failed(format!(
+            "Failed to connect to redis at {}: {}",
+            url, e
+        ))),
+    }
+}
+
+async fn find_item(db: &Database, sku: &str) -> Result<TrackedItem, CommandError> {
+    match db.find_tracked_item(sku).await? {
+        Some(item) => Ok(item),
+        None => Err(CommandError::NotFound(format!(
+            "Item {} is not tracked",
+            sku
+        ))),
+    }
+}
+
+fn backpack_tf(db: &Database) -> Result<BackpackTF, CommandError> {
+    let Ok(user_token) = std::env::var("BPTF_USER_KEY") else {
+        return Err(CommandError::Failed(
+            "BPTF_USER_KEY not set in .env".to_owned(),
+        ));
+    };
+
+    BackpackTF::new(
+        std::env::var("BPTF_API_KEY").unwrap_or_default(),
+        user_token,
+        db.clone(),
+    )
+    .map_err(|_| CommandError::Failed("Failed to create the backpack.tf client".to_owned()))
+}
+
+fn notifier(db: &Database) -> Result<Notifier, CommandError> {
+    Notifier::from_env(db.clone())
+        .map_err(|e| CommandError::Failed(format!("Failed to create the notifier: {}", e)))
+}
+
+/// `pricer sync`, syncs the listings and publishes prices until the process is stopped
+async fn sync(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--items", "--api", "--redis"], &["--no-api"])?;
+    args.no_positional()?;
+
+    let item_str = match args.value("--items") {
+        Some(items) => Some(items.to_owned()),
+        None => std::env::var("ITEMS").ok(),
+    };
+
+    let address = match args.value("--api") {
+        Some(address) => Some(address.to_owned()),
+        None if args.switch("--no-api") => None,
+        None => std::env::var("API_ADDRESS").ok(),
+    };
+
+    let config = PriceConfig::from_env();
+    let db = connect(&args).await?;
+    db.migrate_listing_ids().await?;
+
+    let mut bptf = backpack_tf(&db)?;
+
+    let notifier = notifier(&db)?;
+    bptf.set_notifier(notifier.clone());
+
+    if let Some(recorder) = Recorder::from_env() {
+        bptf.set_recorder(recorder);
+    }
+
+    let cluster = Cluster::from_env(db.clone());
+    bptf.set_cluster(cluster.clone());
+
+    let cluster_runner = cluster.clone();
+    tokio::spawn(async move {
+        cluster_runner.run().await;
+    });
+
+    // the breaker watches the websocket counters, so it runs where the websocket is read
+    let breaker = CircuitBreaker::from_env(db.clone());
+    let breaker_cluster = cluster.clone();
+    tokio::spawn(async move {
+        breaker.run(move || breaker_cluster.is_leader()).await;
+    });
+
+    if let Some(item_str) = item_str {
+        db.add_tracked_items(&parse_tracked_items(&item_str))
+            .await?;
+    }
+
+    // also watches the items imported from a pricelist
+    let tracked_items = db.get_tracked_items().await?;
+    if tracked_items.is_empty() {
+        return Err(CommandError::Usage(
+            "No items to track, pass --items, set ITEMS or import a pricelist".to_owned(),
+        ));
+    }
+
+    let items_owned: Vec<String> = tracked_items.into_iter().map(|item| item.name).collect();
+    let items_ws = items_owned.clone();
+
+    if let Some(address) = address {
+        let feed = Feed::new(db.clone(), config);
+
+        let feed_runner = feed.clone();
+        tokio::spawn(async move {
+            feed_runner.run().await;
+        });
+
+        let state = AppState {
+            db: db.clone(),
+            feed,
+            config,
+            notifier: notifier.clone(),
+            api_token: api::api_token_from_env(),
+        };
+        tokio::spawn(async move {
+            api::serve(&address, state).await;
+        });
+    }
+
+    let bp_other = bptf.clone();
+    let snapshot_cluster = cluster.clone();
+    let expiry_notifier = notifier.clone();
+    tokio::spawn(async move {
+        loop {
+            bptf.watch_snapshots(items_owned.clone()).await;
+            // TODO: probably move this to a new thread
+            if snapshot_cluster.is_leader() {
+                if let Err(e) = db.scan_for_old_listings().await {
+                    error!("Failed to remove the old listings: {:?}", e);
+                }
+                if let Err(e) = expiry_notifier.remove_expired_overrides().await {
+                    error!("Failed to remove the expired overrides: {:?}", e);
+                }
+            }
+            // items that are fresh or owned by another instance return right away
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    });
+
+    // only the leader consumes the websocket, the others wait to take over
+    tokio::spawn(async move {
+        bp_other
+            .keep_websocket(items_ws, std::time::Duration::from_secs(5))
+            .await;
+    });
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+/// `pricer serve`, serves the API until the process is stopped
+async fn serve(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--address", "--redis"], &[])?;
+    args.no_positional()?;
+
+    let address = match args.value("--address") {
+        Some(address) => address.to_owned(),
+        None => match std::env::var("API_ADDRESS") {
+            Ok(address) => address,
+            Err(_) => {
+                return Err(CommandError::Usage(
+                    "No address to serve on, pass --address or set API_ADDRESS".to_owned(),
+                ));
+            }
+        },
+    };
+
+    let config = PriceConfig::from_env();
+    let db = connect(&args).await?;
+    let feed = Feed::new(db.clone(), config);
+    let notifier = notifier(&db)?;
+
+    let feed_runner = feed.clone();
+    tokio::spawn(async move {
+        feed_runner.run().await;
+    });
+
+    let state = AppState {
+        db,
+        feed,
+        config,
+        notifier,
+        api_token: api::api_token_from_env(),
+    };
+    api::serve(&address, state).await;
+    Err(CommandError::Failed("The API server stopped".to_owned()))
+}
+
+/// `pricer price <sku>`
+async fn price(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--redis"], &["--json"])?;
+    let sku = args.sku()?;
+
+    let config = PriceConfig::from_env();
+    let db = connect(&args).await?;
+    let item = find_item(&db, sku).await?;
+    let suggestion = get_suggestion(&db, &config, &item.name).await?;
+    let price_override = db.get_override(&item.name).await?;
+
+    if suggestion.buy.is_none() && suggestion.sell.is_none() && price_override.is_none() {
+        return Err(CommandError::NotFound(format!(
+            "Item {} has no price",
+            item.name
+        )));
+    }
+
+    let key_price = match item.name.as_str() {
+        KEY_ITEM => None,
+        _ => get_key_price(&db, &config).await?,
+    };
+
+    if args.switch("--json") {
+        let mut value = serde_json::to_value(&suggestion).unwrap();
+        value["sku"] = json!(item.sku_or_name());
+        value["price"] = match &price_override {
+            Some(price) => json!(ItemPrice::from_override(&item, price)),
+            None => json!(ItemPrice::new(&item, &suggestion, key_price)),
+        };
+        value["override"] = json!(price_override);
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        return Ok(());
+    }
+
+    println!("{} ({})", item.name, item.sku_or_name());
+    if let Some(price) = &price_override {
+        println!(
+            "overridden by {}, {}: {}",
+            price.author,
+            describe_expiry(price.expires_at),
+            price.reason
+        );
+        println!("buy   {}", price.buy.to_currencies());
+        println!("sell  {}", price.sell.to_currencies());
+        println!("suggestion, not used while overridden:");
+    }
+    print_side(
+        "buy",
+        suggestion.buy,
+        suggestion.buy_listings,
+        key_price,
+        Rounding::DownScrap,
+    );
+    print_side(
+        "sell",
+        suggestion.sell,
+        suggestion.sell_listings,
+        key_price,
+        Rounding::UpScrap,
+    );
+
+    match suggestion.margin {
+        Some(MarginStatus::Widened { buy, sell }) => println!(
+            "widened to the minimum margin from buy {:.2} ref, sell {:.2} ref",
+            buy, sell
+        ),
+        Some(MarginStatus::Unmet { reason }) => {
+            println!("minimum margin not met: {:?}", reason)
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn print_side(
+    side: &str,
+    refined: Option<f32>,
+    listings: usize,
+    key_price: Option<f32>,
+    rounding: Rounding,
+) {
+    let Some(refined) = refined else {
+        println!("{:<5} {:>10}  ({} listings)", side, "-", listings);
+        return;
+    };
+
+    let currencies = ItemCurrencies::from_refined(refined, key_price, rounding);
+    println!(
+        "{:<5} {:>10.2} ref  {} keys {:.2} ref  ({} listings)",
+        side, refined, currencies.keys, currencies.metal, listings
+    );
+}
+
+/// `pricer listings <sku>`
+async fn listings(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--intent", "--redis"], &["--json"])?;
+    let sku = args.sku()?;
+
+    let intent = args.value("--intent");
+    if intent.is_some_and(|intent| intent != "buy" && intent != "sell") {
+        return Err(CommandError::Usage(
+            "--intent has to be buy or sell".to_owned(),
+        ));
+    }
+
+    let db = connect(&args).await?;
+    let item = find_item(&db, sku).await?;
+
+    let mut listings: Vec<_> = db
+        .get_listings_for_item(&item.name)
+        .await?
+        .into_iter()
+        .filter(|listing| intent.is_none_or(|intent| listing.intent == intent))
+        .collect();
+
+    // sells cheapest first, buys highest first, like the book on backpack.tf
+    listings.sort_by(|a, b| {
+        b.intent
+            .cmp(&a.intent)
+            .then_with(|| match a.intent.as_str() {
+                "buy" => b.price.total_cmp(&a.price),
+                _ => a.price.total_cmp(&b.price),
+            })
+    });
+
+    if args.switch("--json") {
+        println!("{}", serde_json::to_string_pretty(&listings).unwrap());
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp();
+    println!(
+        "{:<6} {:>10} {:>8} {:<17} id",
+        "intent", "price", "bumped", "steamid"
+    );
+    for listing in &listings {
+        println!(
+            "{:<6} {:>10.2} {:>7}m {:<17} {}",
+            listing.intent,
+            listing.price,
+            (now - listing.bumped_at as i64) / 60,
+            listing.steamid,
+            listing.id.as_deref().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+/// `pricer snapshot <sku>`
+async fn snapshot(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--redis"], &["--force", "--json"])?;
+    let sku = args.sku()?;
+
+    let db = connect(&args).await?;
+    let item = find_item(&db, sku).await?;
+
+    let mut bptf = backpack_tf(&db)?;
+    bptf.set_notifier(notifier(&db)?);
+
+    let snapshot = if args.switch("--force") {
+        bptf.fetch_snapshot(&item.name).await
+    } else {
+        bptf.get_snapshot(&item.name).await
+    };
+
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(PricingError::IsAlreadyCached) => {
+            eprintln!(
+                "The snapshot of {} was fetched less than a minute ago, pass --force to fetch it anyway",
+                item.name
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(CommandError::Failed(format!(
+                "Failed to fetch the snapshot of {}: {:?}",
+                item.name, e
+            )));
+        }
+    };
+
+    let listings = snapshot.listings.len();
+    let sync = bptf.store_snapshot(&item.name, snapshot).await?;
+
+    if args.switch("--json") {
+        println!("{}", serde_json::to_string_pretty(&sync).unwrap());
+        return Ok(());
+    }
+
+    println!(
+        "{} listings in the snapshot of {}: {} created, {} updated, {} unchanged, {} removed, {} outdated, {} excluded",
+        listings,
+        item.name,
+        sync.created,
+        sync.updated,
+        sync.unchanged,
+        sync.removed,
+        sync.outdated,
+        sync.excluded
+    );
+
+    Ok(())
+}
+
+/// `pricer prune`
+async fn prune(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--max-age", "--redis"], &[])?;
+    args.no_positional()?;
+
+    let max_age = match args.parsed_value::<i64>("--max-age")? {
+        Some(hours) if hours < 1 => {
+            return Err(CommandError::Usage(
+                "--max-age must be at least 1 hour".to_owned(),
+            ))
+        }
+        Some(hours) => match hours.checked_mul(3600) {
+            Some(max_age) => max_age,
+            None => return Err(CommandError::Usage("--max-age is too large".to_owned())),
+        },
+        None => LISTING_MAX_AGE,
+    };
+
+    let db = connect(&args).await?;
+    let removed = db
+        .remove_old_listings(max_age, Utc::now().timestamp())
+        .await?;
+
+    println!("Removed {} listings", removed);
+    Ok(())
+}
+
+/// `pricer export [items|listings|prices]`
+async fn export(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(
+        args,
+        &[
+            "--format", "--skus", "--from", "--to", "--output", "--redis",
+        ],
+        &[],
+    )?;
+
+    let dataset = match args.positional.as_slice() {
+        [] => "items",
+        [dataset] => dataset.as_str(),
+        [_, unexpected, ..] => {
+            return Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            )));
+        }
+    };
+
+    match dataset {
+        "items" => export_items(&args).await,
+        "listings" | "prices" => export_rows(&args, dataset).await,
+        _ => Err(CommandError::Usage(format!("Unknown dataset {}", dataset))),
+    }
+}
+
+async fn export_items(args: &Args) -> CommandResult {
+    for name in ["--format", "--skus", "--from", "--to"] {
+        if args.value(name).is_some() {
+            return Err(CommandError::Usage(format!(
+                "{} only applies to listings and prices",
+                name
+            )));
+        }
+    }
+
+    let config = PriceConfig::from_env();
+    let db = connect(args).await?;
+    let prices = get_item_prices(&db, &config).await?;
+
+    let output = serde_json::to_string_pretty(&json!({
+        "success": true,
+        "currency": null,
+        "items": prices,
+    }))
+    .unwrap();
+
+    match args.value("--output") {
+        Some(path) => std::fs::write(path, output + "\n").map_err(|e| {
+            CommandError::Failed(format!("Failed to write the prices to {}: {}", path, e))
+        }),
+        None => {
+            println!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+async fn export_rows(args: &Args, dataset: &str) -> CommandResult {
+    let format = match args.value("--format") {
+        Some(name) => match ExportFormat::from_name(name) {
+            Some(format) => format,
+            None => return Err(CommandError::Usage(format!("Unknown format {}", name))),
+        },
+        None => ExportFormat::Csv,
+    };
+
+    let time = |name: &str| match args.value(name) {
+        Some(value) => match parse_time(value) {
+            Some(time) => Ok(Some(time)),
+            None => Err(CommandError::Usage(format!("Invalid time {}", value))),
+        },
+        None => Ok(None),
+    };
+    let from = time("--from")?;
+    let to = time("--to")?;
+
+    let skus: Vec<String> = args
+        .value("--skus")
+        .unwrap_or_default()
+        .split(',')
+        .map(|sku| sku.trim().to_owned())
+        .filter(|sku| !sku.is_empty())
+        .collect();
+
+    let db = connect(args).await?;
+    let items = select_items(&db, &skus).await?;
+
+    let writer: Box<dyn Write + Send> = match args.value("--output") {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                return Err(CommandError::Failed(format!(
+                    "Failed to create {}: {}",
+                    path, e
+                )));
+            }
+        },
+        None => Box::new(std::io::stdout()),
+    };
+    let writer = BufWriter::new(writer);
+
+    if dataset == "listings" {
+        let rows = listing_rows(&db, &items, from, to).await?;
+        write_rows(&rows, format, writer)?;
+    } else {
+        let rows = price_rows(&db, &items, from, to).await?;
+        write_rows(&rows, format, writer)?;
+    }
+
+    Ok(())
+}
+
+/// `pricer pricelist import <file>` and `pricer pricelist export`
+async fn pricelist(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--output", "--redis"], &["--force"])?;
+
+    match args.positional.as_slice() {
+        [action, path] if action == "import" => {
+            if args.value("--output").is_some() {
+                return Err(CommandError::Usage(
+                    "--output only applies to export".to_owned(),
+                ));
+            }
+
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| CommandError::Failed(format!("Failed to read {}: {}", path, e)))?;
+            let entries = parse_pricelist(&content).map_err(|e| {
+                CommandError::Failed(format!("{} is not a valid pricelist: {}", path, e))
+            })?;
+
+            let db = connect(&args).await?;
+            let stats =
+                import_pricelist(&db, &notifier(&db)?, entries, args.switch("--force")).await?;
+
+            println!(
+                "Imported {} of {} entries, {} overrides, {} skipped",
+                stats.tracked, stats.entries, stats.overrides, stats.skipped
+            );
+            if stats.kept_overrides > 0 {
+                println!(
+                    "Kept {} overrides set by hand, pass --force to replace them",
+                    stats.kept_overrides
+                );
+            }
+            Ok(())
+        }
+        [action] if action == "import" => {
+            Err(CommandError::Usage("Missing the pricelist file".to_owned()))
+        }
+        [action] if action == "export" => {
+            if args.switch("--force") {
+                return Err(CommandError::Usage(
+                    "--force only applies to import".to_owned(),
+                ));
+            }
+
+            let config = PriceConfig::from_env();
+            let db = connect(&args).await?;
+            let pricelist = build_pricelist(&db, &config).await?;
+            let output = serde_json::to_string_pretty(&pricelist).unwrap();
+
+            match args.value("--output") {
+                Some(path) => std::fs::write(path, output + "\n").map_err(|e| {
+                    CommandError::Failed(format!(
+                        "Failed to write the pricelist to {}: {}",
+                        path, e
+                    ))
+                }),
+                None => {
+                    println!("{}", output);
+                    Ok(())
+                }
+            }
+        }
+        [] => Err(CommandError::Usage("Missing the action".to_owned())),
+        [action, ..] if action != "import" && action != "export" => {
+            Err(CommandError::Usage(format!("Unknown action {}", action)))
+        }
+        [.., unexpected] => Err(CommandError::Usage(format!(
+            "Unexpected argument {}",
+            unexpected
+        ))),
+    }
+}
+
+fn describe_expiry(expires_at: Option<i64>) -> String {
+    match expires_at {
+        Some(time) => format!("expires {}", describe_time(time)),
+        None => "never expires".to_owned(),
+    }
+}
+
+fn describe_time(time: i64) -> String {
+    match DateTime::<Utc>::from_timestamp(time, 0) {
+        Some(time) => time.to_rfc3339(),
+        None => time.to_string(),
+    }
+}
+
+/// Parses a price like `1 key, 10.55 ref` with `tf2-price`
+fn parse_currencies(args: &Args, name: &str) -> Result<ItemCurrencies, CommandError> {
+    let Some(value) = args.value(name) else {
+        return Err(CommandError::Usage(format!("Missing {}", name)));
+    };
+
+    match Currencies::from_str(value) {
+        Ok(currencies) => Ok(currencies.into()),
+        Err(e) => Err(CommandError::Usage(format!(
+            "{} is not a valid price: {}",
+            name, e
+        ))),
+    }
+}
+
+/// `pricer override list|set|remove`
+async fn price_override(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(
+        args,
+        &[
+            "--buy",
+            "--sell",
+            "--reason",
+            "--author",
+            "--expires",
+            "--redis",
+        ],
+        &["--json"],
+    )?;
+
+    let Some((action, rest)) = args.positional.split_first() else {
+        return Err(CommandError::Usage("Missing the action".to_owned()));
+    };
+
+    match action.as_str() {
+        "list" => {
+            if let Some(unexpected) = rest.first() {
+                return Err(CommandError::Usage(format!(
+                    "Unexpected argument {}",
+                    unexpected
+                )));
+            }
+
+            let db = connect(&args).await?;
+            let overrides = db.get_overrides().await?;
+
+            if args.switch("--json") {
+                println!("{}", serde_json::to_string_pretty(&overrides).unwrap());
+                return Ok(());
+            }
+
+            for price in overrides {
+                println!(
+                    "{}: buy {}, sell {} by {}, {}: {}",
+                    price.item,
+                    price.buy.to_currencies(),
+                    price.sell.to_currencies(),
+                    price.author,
+                    describe_expiry(price.expires_at),
+                    price.reason
+                );
+            }
+            Ok(())
+        }
+        "set" => {
+            let sku = match rest {
+                [sku] => sku,
+                [] => return Err(CommandError::Usage("Missing the sku".to_owned())),
+                [_, unexpected, ..] => {
+                    return Err(CommandError::Usage(format!(
+                        "Unexpected argument {}",
+                        unexpected
+                    )));
+                }
+            };
+
+            let buy = parse_currencies(&args, "--buy")?;
+            let sell = parse_currencies(&args, "--sell")?;
+            let Some(reason) = args.value("--reason") else {
+                return Err(CommandError::Usage("Missing --reason".to_owned()));
+            };
+            let author = match args.value("--author") {
+                Some(author) => author.to_owned(),
+                None => std::env::var("USER").unwrap_or_default(),
+            };
+            let expires_at = match args.value("--expires") {
+                Some(value) => match parse_time(value) {
+                    Some(time) => Some(time),
+                    None => {
+                        return Err(CommandError::Usage(
+                            "--expires is not a valid time".to_owned(),
+                        ));
+                    }
+                },
+                None => None,
+            };
+
+            let db = connect(&args).await?;
+            let item = find_item(&db, sku).await?;
+
+            let price = PriceOverride::new(&item.name, buy, sell, &author, reason, expires_at)
+                .map_err(|message| CommandError::Usage(message.to_owned()))?;
+            notifier(&db)?.set_override(&price).await?;
+
+            if args.switch("--json") {
+                println!("{}", serde_json::to_string_pretty(&price).unwrap());
+            } else {
+                println!(
+                    "Pinned {} at buy {}, sell {}, {}",
+                    item.name,
+                    price.buy.to_currencies(),
+                    price.sell.to_currencies(),
+                    describe_expiry(price.expires_at)
+                );
+            }
+            Ok(())
+        }
+        "remove" => {
+            let sku = match rest {
+                [sku] => sku,
+                [] => return Err(CommandError::Usage("Missing the sku".to_owned())),
+                [_, unexpected, ..] => {
+                    return Err(CommandError::Usage(format!(
+                        "Unexpected argument {}",
+                        unexpected
+                    )));
+                }
+            };
+
+            let db = connect(&args).await?;
+            let item = find_item(&db, sku).await?;
+
+            if !notifier(&db)?.remove_override(&item.name).await? {
+                return Err(CommandError::NotFound(format!(
+                    "Item {} is not overridden",
+                    item.name
+                )));
+            }
+
+            println!("Removed the override of {}", item.name);
+            Ok(())
+        }
+        _ => Err(CommandError::Usage(format!("Unknown action {}", action))),
+    }
+}
+
+/// `pricer review list|approve|reject`
+async fn review(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--redis"], &["--json"])?;
+
+    let Some((action, rest)) = args.positional.split_first() else {
+        return Err(CommandError::Usage("Missing the action".to_owned()));
+    };
+
+    if action == "list" {
+        if let Some(unexpected) = rest.first() {
+            return Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            )));
+        }
+
+        let db = connect(&args).await?;
+        let held = db.get_held_prices().await?;
+
+        if args.switch("--json") {
+            println!("{}", serde_json::to_string_pretty(&held).unwrap());
+            return Ok(());
+        }
+
+        for held in held {
+            println!(
+                "{}: buy {:?} -> {:?}, sell {:?} -> {:?} ({:?})",
+                held.item,
+                held.old.as_ref().and_then(|old| old.buy),
+                held.new.buy,
+                held.old.as_ref().and_then(|old| old.sell),
+                held.new.sell,
+                held.reason
+            );
+        }
+        return Ok(());
+    }
+
+    if action != "approve" && action != "reject" {
+        return Err(CommandError::Usage(format!("Unknown action {}", action)));
+    }
+
+    let sku = match rest {
+        [sku] => sku,
+        [] => return Err(CommandError::Usage("Missing the sku".to_owned())),
+        [_, unexpected, ..] => {
+            return Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            )));
+        }
+    };
+
+    let db = connect(&args).await?;
+    let item = find_item(&db, sku).await?;
+
+    let found = if action == "approve" {
+        let notifier = notifier(&db)?;
+        notifier.approve(&item.name).await?.is_some()
+    } else {
+        db.remove_held_price(&item.name).await?
+    };
+
+    if !found {
+        return Err(CommandError::NotFound(format!(
+            "No price of {} waits for review",
+            item.name
+        )));
+    }
+
+    match action.as_str() {
+        "approve" => println!("Published the held price of {}", item.name),
+        _ => println!("Dropped the held price of {}", item.name),
+    }
+    Ok(())
+}
+
+/// `pricer breaker status|trip|reset`
+async fn breaker(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--reason", "--redis"], &["--json"])?;
+
+    let action = match args.positional.as_slice() {
+        [action] => action.as_str(),
+        [] => return Err(CommandError::Usage("Missing the action".to_owned())),
+        [_, unexpected, ..] => {
+            return Err(CommandError::Usage(format!(
+                "Unexpected argument {}",
+                unexpected
+            )));
+        }
+    };
+
+    match action {
+        "status" => {
+            let db = connect(&args).await?;
+            let trip = db.get_breaker().await?;
+
+            if args.switch("--json") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json!({ "tripped": trip })).unwrap()
+                );
+                return Ok(());
+            }
+
+            match trip {
+                Some(trip) => println!(
+                    "Tripped at {} ({:?}): {}",
+                    describe_time(trip.tripped_at),
+                    trip.reason,
+                    trip.message
+                ),
+                None => println!("Not tripped, prices are published"),
+            }
+            Ok(())
+        }
+        "trip" => {
+            let Some(reason) = args.value("--reason") else {
+                return Err(CommandError::Usage("Missing --reason".to_owned()));
+            };
+
+            let db = connect(&args).await?;
+            let trip = BreakerTrip::new(BreakerReason::Manual, reason.to_owned());
+            if !db.trip_breaker(&trip).await? {
+                println!("The circuit breaker is already tripped");
+                return Ok(());
+            }
+
+            println!("Tripped the circuit breaker, prices are frozen");
+            Ok(())
+        }
+        "reset" => {
+            let db = connect(&args).await?;
+            if db.reset_breaker().await? {
+                println!("Reset the circuit breaker, prices are published again");
+            } else {
+                println!("The circuit breaker wasn't tripped");
+            }
+            Ok(())
+        }
+        _ => Err(CommandError::Usage(format!("Unknown action {}", action))),
+    }
+}
+
+/// The archive files or directories passed to `replay` and `backtest`
+fn archive_paths(args: &Args) -> Result<Vec<PathBuf>, CommandError> {
+    if args.positional.is_empty() {
+        return Err(CommandError::Usage("Missing the archive files".to_owned()));
+    }
+
+    let paths: Vec<PathBuf> = args.positional.iter().map(PathBuf::from).collect();
+    match paths.iter().find(|path| !path.exists()) {
+        Some(missing) => Err(CommandError::Failed(format!(
+            "Archive {} doesn't exist",
+            missing.display()
+        ))),
+        None => Ok(paths),
+    }
+}
+
+/// The items in `ITEMS`, `replay` and `backtest` don't read the tracked items from redis
+fn env_items() -> Result<Vec<TrackedItem>, CommandError> {
+    match std::env::var("ITEMS") {
+        Ok(items) => Ok(parse_tracked_items(&items)),
+        Err(_) => Err(CommandError::Usage("ITEMS not set in .env".to_owned())),
+    }
+}
+
+/// `pricer replay`, feeds archived records into a scratch redis
+async fn replay_archive(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(args, &["--speed", "--redis"], &["--allow-live"])?;
+    let paths = archive_paths(&args)?;
+    let speed = args.parsed_value::<f64>("--speed")?;
+    if speed.is_some_and(|speed| speed <= 0.0) {
+        return Err(CommandError::Usage("--speed must be positive".to_owned()));
+    }
+
+    let redis_url = match args.value("--redis") {
+        Some(url) => url.to_owned(),
+        None => match std::env::var("REPLAY_REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                return Err(CommandError::Usage(
+                    "No redis to replay into, pass --redis or set REPLAY_REDIS_URL".to_owned(),
+                ))
+            }
+        },
+    };
+
+    let tracked_items = env_items()?;
+
+    if std::env::var("REDIS_URL").is_ok_and(|url| url == redis_url) {
+        if !args.switch("--allow-live") {
+            return Err(CommandError::Usage(format!(
+                "{} is the live redis, replay into a scratch store or pass --allow-live",
+                redis_url
+            )));
+        }
+        warn!("Replaying into the live redis at {}", redis_url);
+    }
+
+    let db = match Database::try_connect(&redis_url).await {
+        Ok(db) => db,
+        Err(e) => {
+            return Err(CommandError::Failed(format!(
+                "Failed to connect to redis at {}: {}",
+                redis_url, e
+            )))
+        }
+    };
+    db.add_tracked_items(&tracked_items).await?;
+
+    let items = tracked_items.into_iter().map(|item| item.name).collect();
+    let stats = replay(
+        &db,
+        &ReplayOptions {
+            paths,
+            speed,
+            items,
+        },
+    )
+    .await;
+
+    info!(
+        "Replayed {} websocket frames and {} snapshots ({} invalid), removed {} old listings",
+        stats.frames, stats.snapshots, stats.invalid_snapshots, stats.pruned
+    );
+    Ok(())
+}
+
+/// `pricer backtest`, scores the pricing strategies on archived listings
+fn backtest_archive(args: Vec<String>) -> CommandResult {
+    let args = Args::parse(
+        args,
+        &["--from", "--to", "--step", "--horizon", "--strategy"],
+        &["--json"],
+    )?;
+    let paths = archive_paths(&args)?;
+
+    let time = |name: &str| match args.value(name) {
+        Some(value) => match parse_time(value) {
+            Some(time) => Ok(Some(time)),
+            None => Err(CommandError::Usage(format!("Invalid time {}", value))),
+        },
+        None => Ok(None),
+    };
+    let from = time("--from")?;
+    let to = time("--to")?;
+
+    let step = args.parsed_value::<u32>("--step")?.unwrap_or(300);
+    let horizon = args.parsed_value::<u32>("--horizon")?.unwrap_or(3600);
+
+    let mut strategies = Vec::new();
+    for name in args.all_values("--strategy") {
+        match Strategy::from_name(name) {
+            Some(strategy) => strategies.push(strategy),
+            None => {
+                return Err(CommandError::Usage(format!("Unknown strategy {}", name)));
+            }
+        }
+    }
+    if strategies.is_empty() {
+        strategies = Strategy::ALL.to_vec();
+    }
+
+    let items = env_items()?.into_iter().map(|item| item.name).collect();
+
+    let reports = backtest(
+        &BacktestOptions {
+            paths,
+            items,
+            strategies,
+            from,
+            to,
+            step: step.max(1) as i64,
+            horizon: horizon as i64,
+        },
+        &ListingFilter::from_env(),
+    );
+
+    if args.switch("--json") {
+        println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+        return Ok(());
+    }
+
+    println!(
+        "{:<10} {:>11} {:>8} {:>9} {:>9} {:>8} {:>12}",
+        "strategy", "suggestions", "checked", "undercut", "overpaid", "changes", "mean change"
+    );
+    for report in reports {
+        println!(
+            "{:<10} {:>11} {:>8} {:>8.1}% {:>8.1}% {:>8} {:>11.2}%",
+            report.strategy,
+            report.suggestions,
+            report.checked,
+            report.undercut_rate * 100.0,
+            report.overpaid_rate * 100.0,
+            report.changes,
+            report.mean_change
+        );
+    }
+
+    Ok(())
+}
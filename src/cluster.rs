@@ -0,0 +1,186 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use log::{error, info};
+use tokio::sync::watch;
+
+use crate::db::Database;
+
+/// Lease that makes its holder the leader, the leader consumes the websocket
+const LEADER_LEASE_KEY: &str = "cluster:leader";
+
+/// Default lifetime of the leases in seconds, instances without a heartbeat for this long are considered dead
+const DEFAULT_LEASE_TTL: u64 = 30;
+
+fn snapshot_lease_key(item: &str) -> String {
+    format!("cluster:lease:snapshot:{}", item)
+}
+
+/// Coordinates several pricer processes that share one redis
+///
+/// Every tracked item is owned by exactly one of the live instances, picked by rendezvous
+/// hashing, so ownership moves on its own when instances join or die. The owner also has
+/// to hold the item's snapshot lease, which keeps the old and the new owner from both
+/// refreshing the item while the instances still disagree about who is alive
+#[derive(Clone)]
+pub struct Cluster {
+    db: Database,
+    instance_id: String,
+    /// Lease lifetime in seconds
+    lease_ttl: u64,
+    instances: Arc<RwLock<Vec<String>>>,
+    leader: Arc<watch::Sender<bool>>,
+}
+
+impl Cluster {
+    pub fn new(db: Database, instance_id: String, lease_ttl: u64) -> Self {
+        Self {
+            db,
+            instances: Arc::new(RwLock::new(vec![instance_id.clone()])),
+            instance_id,
+            lease_ttl,
+            leader: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Reads `INSTANCE_ID` (defaults to the hostname and process id) and `CLUSTER_LEASE_TTL`
+    pub fn from_env(db: Database) -> Self {
+        let instance_id = match std::env::var("INSTANCE_ID") {
+            Ok(instance_id) => instance_id,
+            Err(_) => format!(
+                "{}-{}",
+                std::env::var("HOSTNAME").unwrap_or_else(|_| "pricer".to_owned()),
+                std::process::id()
+            ),
+        };
+
+        let lease_ttl = match std::env::var("CLUSTER_LEASE_TTL") {
+            Ok(ttl) => ttl.parse().expect("CLUSTER_LEASE_TTL is not a number"),
+            Err(_) => DEFAULT_LEASE_TTL,
+        };
+        // redis refuses leases that expire right away, no instance could become the leader
+        if lease_ttl < 1 {
+            panic!(
+                "CLUSTER_LEASE_TTL must be at least 1 second, got {}",
+                lease_ttl
+            );
+        }
+
+        Self::new(db, instance_id, lease_ttl)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.leader.borrow()
+    }
+
+    /// Sends heartbeats and renews the leader lease until the process exits
+    pub async fn run(&self) {
+        info!("Running as cluster instance {}", self.instance_id);
+
+        loop {
+            self.tick().await;
+            tokio::time::sleep(Duration::from_secs((self.lease_ttl / 3).max(1))).await;
+        }
+    }
+
+    async fn tick(&self) {
+        match self.db.heartbeat(&self.instance_id, self.lease_ttl).await {
+            Ok(mut instances) => {
+                if !instances.contains(&self.instance_id) {
+                    instances.push(self.instance_id.clone());
+                }
+
+                let mut current = self.instances.write().unwrap();
+                if *current != instances {
+                    info!("Live cluster instances: {:?}", instances);
+                    *current = instances;
+                }
+            }
+            Err(e) => {
+                error!("Failed to send cluster heartbeat: {:?}", e);
+            }
+        }
+
+        let is_leader = match self
+            .db
+            .acquire_lease(LEADER_LEASE_KEY, &self.instance_id, self.lease_ttl * 1000)
+            .await
+        {
+            Ok(is_leader) => is_leader,
+            Err(e) => {
+                // without redis we can't be sure nobody else took over
+                error!("Failed to renew the leader lease: {:?}", e);
+                false
+            }
+        };
+
+        self.leader.send_if_modified(|leader| {
+            if *leader == is_leader {
+                return false;
+            }
+
+            if is_leader {
+                info!("Instance {} is the leader now", self.instance_id);
+            } else {
+                info!("Instance {} lost the leadership", self.instance_id);
+            }
+
+            *leader = is_leader;
+            true
+        });
+    }
+
+    /// Waits until this instance is the leader
+    pub async fn wait_for_leadership(&self) {
+        let mut leader = self.leader.subscribe();
+        let _ = leader.wait_for(|leader| *leader).await;
+    }
+
+    /// Waits until this instance stops being the leader
+    pub async fn wait_for_lost_leadership(&self) {
+        let mut leader = self.leader.subscribe();
+        let _ = leader.wait_for(|leader| !*leader).await;
+    }
+
+    /// Returns whether the item belongs to this instance among the live instances
+    pub fn owns(&self, item: &str) -> bool {
+        let instances = self.instances.read().unwrap();
+
+        let owner = instances
+            .iter()
+            .max_by_key(|instance| md5::compute(format!("{}:{}", instance, item)).0);
+
+        owner.is_none_or(|owner| *owner == self.instance_id)
+    }
+
+    /// Claims the snapshot refresh of the item, `false` if another instance owns it
+    pub async fn claim_item(&self, item: &str) -> bool {
+        let key = snapshot_lease_key(item);
+
+        if !self.owns(item) {
+            // hand the item over right away instead of letting the lease run out
+            if let Err(e) = self.db.release_lease(&key, &self.instance_id).await {
+                error!("Failed to release the snapshot lease of {}: {:?}", item, e);
+            }
+            return false;
+        }
+
+        match self
+            .db
+            .acquire_lease(&key, &self.instance_id, self.lease_ttl * 1000)
+            .await
+        {
+            Ok(acquired) => acquired,
+            Err(e) => {
+                error!("Failed to acquire the snapshot lease of {}: {:?}", item, e);
+                false
+            }
+        }
+    }
+}
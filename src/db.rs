@@ -1,16 +1,250 @@
-use log::{error, info, warn};
-use redis::{aio::MultiplexedConnection, AsyncCommands, Client, RedisError};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use log::{debug, error, info, warn};
+use redis::{
+    aio::{MultiplexedConnection, PubSub},
+    AsyncCommands, Client, ErrorKind, RedisError, Script,
+};
+use serde::Serialize;
 use serde_json::Value;
 
 use crate::{
-    event::{EventListing, EventListingDeletion, UniversalItem, UniversalListing},
+    book::{is_expired, UpsertResult, LISTING_MAX_AGE, TOMBSTONE_TTL},
+    event::{
+        listing_id, listing_key, EventListing, EventListingDeletion, UniversalItem,
+        UniversalListing,
+    },
+    filter::{ExclusionReason, ListingFilter},
+    guard::{BreakerTrip, HeldPrice},
+    items::TrackedItem,
+    notifier::PriceChange,
+    pricelist::PricelistEntry,
+    pricing::{PriceOverride, PriceSuggestion},
+    publish::{
+        ListingChange, OverrideChange, LISTINGS_CHANNEL, LISTINGS_STREAM, OVERRIDES_CHANNEL,
+        PRICES_CHANNEL, PRICES_STREAM,
+    },
     types::Listing,
 };
 
+const EXCLUSION_STATS_KEY: &str = "stats:excluded";
+const WEBHOOK_DEAD_LETTER_KEY: &str = "webhooks:dead_letter";
+const TRACKED_ITEMS_KEY: &str = "tracked_items";
+/// Hash of item name to the tf2autobot pricelist settings of the item
+const PRICELIST_ENTRIES_KEY: &str = "pricelist:entries";
+/// Hash of item name to its manual price
+const OVERRIDES_KEY: &str = "overrides";
+/// Hash of item name to the suggestion the price guard held back
+const REVIEW_QUEUE_KEY: &str = "review:pending";
+/// Set while the circuit breaker is tripped
+const BREAKER_KEY: &str = "circuit_breaker";
+const EVENT_STATS_KEY: &str = "stats:events";
+const EVENT_DEAD_LETTER_KEY: &str = "events:dead_letter";
+/// Hash of item name to the time its last snapshot got fetched
+const SNAPSHOT_FETCHED_AT_KEY: &str = "snapshots:fetched_at";
+/// Hash of item name to the `created_at` of its last snapshot
+const SNAPSHOT_CREATED_AT_KEY: &str = "snapshots:created_at";
+/// Maximum number of undecodable events that are kept around
+const EVENT_DEAD_LETTER_LIMIT: isize = 10_000;
+
+/// Set once the stored listings were moved to the canonical listing ids
+const LISTING_ID_MIGRATION_KEY: &str = "migrations:listing_ids";
+
+/// Sorted set of the running pricer instances scored by their last heartbeat
+const CLUSTER_INSTANCES_KEY: &str = "cluster:instances";
+
+/// Writes a batch of listings, skipping the ones where the stored listing was bumped later
+/// or the listing got deleted after it was bumped, see `book::upsert_result`
+///
+/// KEYS come in groups of three per listing: the listing key, its tombstone key and the item index
+/// ARGV come in pairs per listing: the serialized listing and its `bumped_at`
+///
+/// Returns one result per listing: 1 if it got created, 2 if it got updated, 0 if it didn't
+/// change, -1 if it was deleted after the update and -2 if the stored listing is newer
+const UPSERT_LISTINGS_SCRIPT: &str = r#"
+local results = {}
+
+for i = 1, #ARGV / 2 do
+    local key = KEYS[i * 3 - 2]
+    local value = ARGV[i * 2 - 1]
+    local bumped_at = tonumber(ARGV[i * 2])
+    local result = 2
+
+    local deleted_at = redis.call('GET', KEYS[i * 3 - 1])
+    local current = redis.call('GET', key)
+    if deleted_at and tonumber(deleted_at) >= bumped_at then
+        result = -1
+    elseif not current then
+        result = 1
+    elseif current == value then
+        result = 0
+    else
+        local ok, stored = pcall(cjson.decode, current)
+        if ok and type(stored) == 'table' and tonumber(stored['bumped_at']) and tonumber(stored['bumped_at']) > bumped_at then
+            result = -2
+        end
+    end
+
+    if result > 0 then
+        redis.call('SET', key, value)
+    end
+    if result >= 0 then
+        redis.call('SADD', KEYS[i * 3], key)
+    end
+
+    results[i] = result
+end
+
+return results
+"#;
+
+/// Removes the listings that vanished from a snapshot, unless they were bumped after the
+/// snapshot got created
+///
+/// KEYS[1] is the item index, the remaining keys are the listing keys
+/// ARGV[1] is the `created_at` of the snapshot
+///
+/// Returns the keys of the listings that got removed
+const REMOVE_VANISHED_LISTINGS_SCRIPT: &str = r#"
+local removed = {}
+
+for i = 2, #KEYS do
+    local current = redis.call('GET', KEYS[i])
+    if not current then
+        redis.call('SREM', KEYS[1], KEYS[i])
+    else
+        local ok, stored = pcall(cjson.decode, current)
+        if not (ok and type(stored) == 'table' and tonumber(stored['bumped_at']) and tonumber(stored['bumped_at']) > tonumber(ARGV[1])) then
+            redis.call('DEL', KEYS[i])
+            redis.call('SREM', KEYS[1], KEYS[i])
+            table.insert(removed, KEYS[i])
+        end
+    end
+end
+
+return removed
+"#;
+
+/// Takes or renews a lease
+///
+/// KEYS[1] is the lease key
+/// ARGV[1] is the owner, ARGV[2] the lease duration in milliseconds
+///
+/// Returns 1 if the owner holds the lease now
+const ACQUIRE_LEASE_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if owner == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+
+if not owner then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+
+return 0
+"#;
+
+/// Gives up a lease if it is still held by the owner
+///
+/// KEYS[1] is the lease key, ARGV[1] the owner
+const RELEASE_LEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+
+return 0
+"#;
+
+/// Counts of what a snapshot changed in the stored listings of its item
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct SnapshotSync {
+    pub created: usize,
+    pub updated: usize,
+    /// Stored listings that weren't part of the snapshot anymore
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Listings that were bumped or deleted after the snapshot
+    pub outdated: usize,
+    pub excluded: usize,
+}
+
+impl SnapshotSync {
+    pub fn has_changes(&self) -> bool {
+        self.created > 0 || self.updated > 0 || self.removed > 0
+    }
+}
+
+/// When the snapshot of an item was fetched last and when backpack.tf created it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotFreshness {
+    pub item: String,
+    pub fetched_at: Option<i64>,
+    pub created_at: Option<u32>,
+}
+
+/// A listing that is about to be written by `Database::upsert_listings`
+struct ListingWrite {
+    key: String,
+    item: String,
+    listing: UniversalListing,
+}
+
+/// Key of the set that holds the keys of all the listings of the given item name
+fn item_listings_key(item: &str) -> String {
+    format!("item_listings:{}", item)
+}
+
+/// Key that remembers when the listing got deleted
+fn tombstone_key(key: &str) -> String {
+    format!("tombstone:{}", key)
+}
+
+fn parse_override(item: &str, value: &str) -> Option<PriceOverride> {
+    match serde_json::from_str(value) {
+        Ok(price) => Some(price),
+        Err(e) => {
+            warn!("Failed to deserialize override of item {}: {:?}", item, e);
+            None
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Database {
     client: Client,
     conn: MultiplexedConnection,
+    filter: ListingFilter,
+    /// Approximate maximum length of the listing and price streams
+    stream_maxlen: usize,
+    upsert_script: Script,
+    remove_vanished_script: Script,
+    acquire_lease_script: Script,
+    release_lease_script: Script,
+}
+
+/// Counts the listings that were kept out of the database during one update
+#[derive(Debug, Default)]
+struct Exclusions {
+    ignored: u64,
+    blocked: u64,
+}
+
+impl Exclusions {
+    fn add(&mut self, reason: ExclusionReason) {
+        match reason {
+            ExclusionReason::Ignored => self.ignored += 1,
+            ExclusionReason::Blocked => self.blocked += 1,
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.ignored + self.blocked
+    }
 }
 
 impl Database {
@@ -23,31 +257,211 @@ impl Database {
             }
         };
 
-        let client = match Client::open(url) {
-            Ok(client) => client,
+        Self::connect(&url).await
+    }
+
+    /// Connects to the redis server at the given url, the other settings are still read from the env
+    pub async fn connect(url: &str) -> Self {
+        match Self::try_connect(url).await {
+            Ok(db) => db,
             Err(e) => {
                 panic!("Failed to connect to redis: {:?}", e);
             }
-        };
+        }
+    }
+
+    /// Like `connect`, but returns the error instead of panicking
+    pub async fn try_connect(url: &str) -> Result<Self, RedisError> {
+        let client = Client::open(url)?;
+        let conn = client.get_multiplexed_tokio_connection().await?;
 
         info!("Successfully connected to the redis server");
 
-        let conn = match client.get_multiplexed_tokio_connection().await {
-            Ok(conn) => conn,
-            Err(e) => {
-                panic!("Failed to get connection to redis: {:?}", e);
-            }
+        let stream_maxlen = match std::env::var("REDIS_STREAM_MAXLEN") {
+            Ok(maxlen) => maxlen.parse().expect("REDIS_STREAM_MAXLEN is not a number"),
+            Err(_) => 100_000,
+        };
+
+        let db = Self {
+            client,
+            conn,
+            filter: ListingFilter::from_env(),
+            stream_maxlen,
+            upsert_script: Script::new(UPSERT_LISTINGS_SCRIPT),
+            remove_vanished_script: Script::new(REMOVE_VANISHED_LISTINGS_SCRIPT),
+            acquire_lease_script: Script::new(ACQUIRE_LEASE_SCRIPT),
+            release_lease_script: Script::new(RELEASE_LEASE_SCRIPT),
         };
 
-        Self { client, conn }
+        if let Ok(groups) = std::env::var("REDIS_STREAM_GROUPS") {
+            for group in groups.split(',').map(|group| group.trim()) {
+                if group.is_empty() {
+                    continue;
+                }
+
+                db.create_stream_group(group).await?;
+            }
+        }
+
+        Ok(db)
+    }
+
+    /// Creates the consumer group on the listing and price streams, starting at the
+    /// end of the streams. Groups that already exist are left untouched
+    pub async fn create_stream_group(&self, group: &str) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+
+        for stream in [LISTINGS_STREAM, PRICES_STREAM] {
+            match conn
+                .xgroup_create_mkstream::<_, _, _, ()>(stream, group, "$")
+                .await
+            {
+                Ok(()) => info!("Created consumer group {} on stream {}", group, stream),
+                Err(e) if e.code() == Some("BUSYGROUP") => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the payload on the channel and appends it to the stream in one round trip
+    async fn publish(&self, channel: &str, stream: &str, payload: &str) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        self.add_publish(&mut pipe, channel, stream, payload);
+
+        pipe.query_async(&mut conn).await
+    }
+
+    /// Adds the commands that publish the payload on the channel and append it to the stream
+    fn add_publish(&self, pipe: &mut redis::Pipeline, channel: &str, stream: &str, payload: &str) {
+        pipe.publish(channel, payload)
+            .ignore()
+            .cmd("XADD")
+            .arg(stream)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.stream_maxlen)
+            .arg("*")
+            .arg("data")
+            .arg(payload)
+            .ignore();
+    }
+
+    /// Publishes the listing changes on the listing channel and stream in one round trip
+    ///
+    /// Failures are only logged since the listings themselves are already stored
+    pub async fn publish_listing_changes(&self, changes: Vec<ListingChange>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for change in &changes {
+            let payload = serde_json::to_string(change).unwrap();
+            self.add_publish(&mut pipe, LISTINGS_CHANNEL, LISTINGS_STREAM, &payload);
+        }
+
+        if let Err(e) = pipe.query_async::<_, ()>(&mut conn).await {
+            error!(
+                "Failed to publish {} listing changes: {:?}",
+                changes.len(),
+                e
+            );
+        }
+    }
+
+    /// Publishes a price change on the price channel and stream
+    pub async fn publish_price_change<T: Serialize>(&self, change: &T) -> Result<(), RedisError> {
+        let payload = serde_json::to_string(change).unwrap();
+
+        self.publish(PRICES_CHANNEL, PRICES_STREAM, &payload).await
+    }
+
+    /// Get how many listings were excluded so far, keyed by the exclusion reason
+    pub async fn get_exclusion_counts(&self) -> Result<HashMap<String, u64>, RedisError> {
+        let mut conn = self.conn.clone();
+        conn.hgetall(EXCLUSION_STATS_KEY).await
+    }
+
+    /// Adds the exclusions of one update to the counters stored in redis
+    async fn record_exclusions(
+        conn: &mut MultiplexedConnection,
+        exclusions: &Exclusions,
+    ) -> Result<(), RedisError> {
+        for (reason, count) in [
+            (ExclusionReason::Ignored, exclusions.ignored),
+            (ExclusionReason::Blocked, exclusions.blocked),
+        ] {
+            if count > 0 {
+                conn.hincr::<_, _, _, ()>(EXCLUSION_STATS_KEY, reason.as_str(), count)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes all the listings that are newer than the stored ones and weren't deleted since,
+    /// in a single round trip
+    async fn upsert_listings(
+        &self,
+        writes: &[ListingWrite],
+    ) -> Result<Vec<UpsertResult>, RedisError> {
+        if writes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.conn.clone();
+        let mut invocation = self.upsert_script.prepare_invoke();
+        for write in writes {
+            invocation
+                .key(&write.key)
+                .key(tombstone_key(&write.key))
+                .key(item_listings_key(&write.item))
+                .arg(serde_json::to_string(&write.listing).unwrap())
+                .arg(write.listing.bumped_at);
+        }
+
+        let results: Vec<i64> = invocation.invoke_async(&mut conn).await?;
+
+        Ok(results.into_iter().map(UpsertResult::from).collect())
+    }
+
+    /// Removes the listings of excluded steamids, they might have been stored before the
+    /// steamid got excluded. Takes pairs of listing key and item name
+    async fn remove_excluded(
+        &self,
+        excluded: &[(String, String)],
+    ) -> Result<Vec<ListingChange>, RedisError> {
+        if excluded.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for (key, item) in excluded {
+            pipe.del(key).srem(item_listings_key(item), key).ignore();
+        }
+
+        let removed: Vec<u32> = pipe.query_async(&mut conn).await?;
+
+        Ok(excluded
+            .iter()
+            .zip(removed)
+            .filter(|(_, removed)| *removed > 0)
+            .map(|((key, item), _)| ListingChange::delete(key, Some(item)))
+            .collect())
     }
 
     pub async fn store_listings(&mut self, listings: Vec<EventListing>) {
         for listing in listings {
             let id = listing.id.clone();
 
-            let key = format!("listing:{}:{}", listing.item.defindex, listing.id);
             let db_listing: UniversalListing = listing.into();
+            let key = db_listing.key();
             let value = serde_json::to_string(&db_listing).unwrap();
 
             match self.conn.set(key, value).await {
@@ -87,116 +501,181 @@ impl Database {
         Ok(())
     }
 
-    pub async fn handle_delete_events(&mut self, listings: Vec<EventListingDeletion>) {
-        let mut deleted = 0;
+    /// Deletes the listings and leaves a tombstone for each, only the listings that
+    /// actually existed are published as deleted
+    ///
+    /// `deleted_at` is the unix timestamp the deletions were received at, updates bumped
+    /// before it can't bring the listings back
+    pub async fn handle_delete_events(
+        &self,
+        listings: Vec<EventListingDeletion>,
+        deleted_at: i64,
+    ) -> Result<(), RedisError> {
+        if listings.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        let mut deleted = Vec::with_capacity(listings.len());
         for listing in listings {
-            let key = format!("listing:{}:{}", listing.item.defindex, listing.id);
+            let key = listing_key(listing.item.defindex, &listing.canonical_id());
 
-            match self.conn.del(key).await {
-                Ok(()) => {
-                    deleted += 1;
-                    //info!("Deleted listing with id {}", listing.id);
-                }
-                Err(e) => {
-                    panic!("Failed to delete listing with id {}: {:?}", listing.id, e);
-                }
-            }
-        }
+            // the tombstone keeps older snapshots from bringing the listing back
+            pipe.srem(item_listings_key(&listing.item.name), &key)
+                .ignore()
+                .del(&key)
+                .set_ex(tombstone_key(&key), deleted_at, TOMBSTONE_TTL as u64)
+                .ignore();
 
-        if deleted > 0 {
-            info!("Deleted {} listings", deleted);
+            deleted.push((key, listing.item.name));
         }
+
+        let removed: Vec<u32> = pipe.query_async(&mut conn).await?;
+
+        info!(
+            "Deleted {} of {} listings",
+            removed.iter().sum::<u32>(),
+            deleted.len()
+        );
+
+        let changes = deleted
+            .iter()
+            .zip(removed)
+            .filter(|(_, removed)| *removed > 0)
+            .map(|((key, item), _)| ListingChange::delete(key, Some(item)))
+            .collect();
+
+        self.publish_listing_changes(changes).await;
+
+        Ok(())
     }
 
     pub async fn update_listings_from_websocket(
-        &mut self,
+        &self,
         listings: Vec<EventListing>,
     ) -> Result<(), RedisError> {
-        let mut updated = 0;
-        let mut created = 0;
+        let started = Instant::now();
+        let received = listings.len();
+        let mut exclusions = Exclusions::default();
+        let mut excluded = Vec::new();
+        let mut writes = Vec::with_capacity(listings.len());
 
         for listing in listings {
-            let key = format!("listing:{}:{}", listing.item.defindex, listing.id);
+            let item = listing.item.name.clone();
             let db_listing: UniversalListing = listing.into();
-            let value = serde_json::to_string(&db_listing).unwrap();
+            let key = db_listing.key();
 
-            match self.conn.exists(key.clone()).await {
-                Ok(exists) => {
-                    if exists {
-                        updated += 1;
-                    } else {
-                        created += 1;
-                    }
-                }
-                Err(e) => {
-                    panic!(
-                        "Failed to check if listing with key {} exists: {:?}",
-                        key, e
-                    );
-                }
+            if let Some(reason) = self.filter.check(&db_listing.steamid) {
+                exclusions.add(reason);
+                excluded.push((key, item));
+                continue;
             }
 
-            match self.conn.set(key.clone(), value).await {
-                Ok(()) => {
-                    //info!("Modified listing with key {}", key);
-                }
-                Err(e) => {
-                    panic!("Failed to update listing with key {}: {:?}", key, e);
+            writes.push(ListingWrite {
+                key,
+                item,
+                listing: db_listing,
+            });
+        }
+
+        let mut changes = self.remove_excluded(&excluded).await?;
+        let results = self.upsert_listings(&writes).await?;
+
+        let mut updated = 0;
+        let mut created = 0;
+        let mut outdated = 0;
+        for (write, result) in writes.into_iter().zip(results) {
+            match result {
+                UpsertResult::Created => created += 1,
+                UpsertResult::Updated => updated += 1,
+                UpsertResult::Unchanged => continue,
+                UpsertResult::Deleted | UpsertResult::Outdated => {
+                    outdated += 1;
+                    continue;
                 }
             }
+
+            changes.push(ListingChange::update(
+                &write.key,
+                Some(&write.item),
+                write.listing,
+            ));
         }
 
-        if updated > 0 || created > 0 {
+        if updated > 0 || created > 0 || outdated > 0 {
             info!(
-                "Updated {} listings, created {} listings from websocket",
-                updated, created
+                "Updated {} listings, created {} listings, rejected {} outdated listings from websocket",
+                updated, created, outdated
             );
         }
 
+        if exclusions.total() > 0 {
+            info!(
+                "Excluded {} listings from websocket (ignored: {}, blocked: {})",
+                exclusions.total(),
+                exclusions.ignored,
+                exclusions.blocked
+            );
+            Self::record_exclusions(&mut self.conn.clone(), &exclusions).await?;
+        }
+
+        self.publish_listing_changes(changes).await;
+
+        debug!(
+            "Wrote {} websocket listings in {:?}",
+            received,
+            started.elapsed()
+        );
+
         Ok(())
     }
 
     pub async fn scan_for_old_listings(&self) -> Result<(), RedisError> {
-        let mut conn = match self.client.get_multiplexed_tokio_connection().await {
-            Ok(conn) => conn,
-            Err(e) => {
-                panic!("Failed to get connection to redis: {:?}", e);
-            }
-        };
+        self.remove_old_listings(LISTING_MAX_AGE, chrono::Utc::now().timestamp())
+            .await?;
+        Ok(())
+    }
 
-        let keys: Vec<String> = match conn.keys("listing:*").await {
-            Ok(keys) => keys,
-            Err(e) => {
-                panic!("Failed to get keys from redis: {:?}", e);
-            }
-        };
+    /// Removes the listings that weren't bumped within `max_age` seconds before `now`,
+    /// returns how many got removed
+    ///
+    /// `now` is a unix timestamp, replays pass the time of the record they are at
+    pub async fn remove_old_listings(&self, max_age: i64, now: i64) -> Result<usize, RedisError> {
+        let mut conn = self.conn.clone();
+        let mut scan_conn = self.conn.clone();
+        let mut keys = scan_conn.scan_match::<_, String>("listing:*").await?;
 
         let mut deleted = 0;
+        let mut changes = Vec::new();
 
-        for key in keys {
-            let value: String = match conn.get(&key).await {
-                Ok(value) => value,
-                Err(e) => {
-                    warn!("Failed to get value from redis: {:?} - key: {:?}", e, key);
-                    //panic!();
-                    continue;
-                }
+        while let Some(key) = keys.next_item().await {
+            let value: Option<String> = conn.get(&key).await?;
+            let Some(value) = value else {
+                continue;
             };
 
             let db_listing: UniversalListing = match serde_json::from_str(&value) {
                 Ok(db_listing) => db_listing,
                 Err(e) => {
-                    panic!("Failed to deserialize listing: {:?}", e);
+                    warn!("Failed to deserialize listing {}: {:?}", key, e);
+                    continue;
                 }
             };
 
-            if db_listing.bumped_at < (chrono::Utc::now().timestamp() - 86400) as u32 {
-                conn.del::<&str, bool>(&key).await.unwrap();
+            if is_expired(&db_listing, max_age, now) {
+                conn.del::<&str, bool>(&key).await?;
                 deleted += 1;
+                if let Some(item) = &db_listing.item.name {
+                    conn.srem::<_, _, ()>(item_listings_key(item), &key).await?;
+                }
+                changes.push(ListingChange::delete(&key, db_listing.item.name.as_deref()));
                 info!(
                     "Deleted listing with key {}, reason: too old (bumped {}m ago), {:?}",
                     key,
-                    (chrono::Utc::now().timestamp() - db_listing.bumped_at as i64) / 60,
+                    (now - db_listing.bumped_at as i64) / 60,
                     db_listing
                 );
             }
@@ -206,91 +685,129 @@ impl Database {
             info!("Deleted {} listings that were too old", deleted);
         }
 
-        Ok(())
+        self.publish_listing_changes(changes).await;
+
+        Ok(deleted)
     }
 
     /// Updates all entries in the database from a snapshot by finding already existing entries
     /// or creating new ones
     ///
-    /// A listing update does not automatically mean that anything has changed since it always writes the listing to the database
+    /// The snapshot is authoritative, stored listings of the item that aren't part of it anymore
+    /// get removed. Listings that were bumped or deleted after the snapshot was created
+    /// (`created_at`) are left untouched
     pub async fn update_listings_from_snapshot(
         &self,
         listings: Vec<Listing>,
         item: &str,
-    ) -> Result<(), RedisError> {
-        let mut updated = 0;
-        let mut created = 0;
-        let mut con = match self.client.get_multiplexed_tokio_connection().await {
-            Ok(con) => con,
-            Err(e) => {
-                panic!("Failed to get connection to redis: {:?}", e);
-            }
-        };
+        created_at: u32,
+    ) -> Result<SnapshotSync, RedisError> {
+        let started = Instant::now();
+        let mut sync = SnapshotSync::default();
+        let mut exclusions = Exclusions::default();
+        let mut excluded = Vec::new();
+        let mut writes = Vec::with_capacity(listings.len());
+        let mut seen = HashSet::new();
+        let mut con = self.conn.clone();
 
-        let listing_len = listings.len();
-        for listing in listings {
+        for mut listing in listings {
             // skip listings without a user agent aka. not a bot
             if listing.user_agent.is_none() {
                 continue;
             }
 
-            let key = if listing.intent == "sell" {
-                let item_id = match listing.item.id {
-                    Some(id) => id,
-                    None => {
-                        panic!("Listing with intent sell has no item id");
-                    }
-                };
-                let id = format!("440_{}", item_id);
-                format!("listing:{}:{}", listing.item.defindex, id)
-            } else {
-                let id = format!("440_{}_{:x}", listing.steamid, md5::compute(item));
-                format!("listing:{}:{}", listing.item.defindex, id)
-            };
-
+            listing.item.name = Some(item.to_owned());
             let db_value: UniversalListing = listing.into();
-            let value = serde_json::to_string(&db_value).unwrap();
-
-            // TODO: remove this, debug only
-            match con.exists(key.clone()).await {
-                Ok(exists) => {
-                    if exists {
-                        let v: String = con.get(&key).await.unwrap();
-
-                        if v != value {
-                            updated += 1;
-                        }
-                    } else {
-                        created += 1;
-                    }
-                }
-                Err(e) => {
-                    panic!(
-                        "Failed to check if listing with key {} exists: {:?}",
-                        key, e
-                    );
-                }
+            let key = db_value.key();
+
+            seen.insert(key.clone());
+
+            if let Some(reason) = self.filter.check(&db_value.steamid) {
+                exclusions.add(reason);
+                excluded.push((key, item.to_owned()));
+                continue;
             }
 
-            match con.set(key.clone(), value).await {
-                Ok(()) => {
-                    //info!("Modified listing with key {}", key);
+            writes.push(ListingWrite {
+                key,
+                item: item.to_owned(),
+                listing: db_value,
+            });
+        }
+
+        let mut changes = self.remove_excluded(&excluded).await?;
+        let results = self.upsert_listings(&writes).await?;
+
+        for (write, result) in writes.into_iter().zip(results) {
+            match result {
+                UpsertResult::Created => sync.created += 1,
+                UpsertResult::Updated => sync.updated += 1,
+                UpsertResult::Unchanged => {
+                    sync.unchanged += 1;
+                    continue;
                 }
-                Err(e) => {
-                    panic!("Failed to update listing with key {}: {:?}", key, e);
+                UpsertResult::Deleted | UpsertResult::Outdated => {
+                    // a newer websocket update or delete already happened
+                    sync.outdated += 1;
+                    continue;
                 }
             }
+
+            changes.push(ListingChange::update(&write.key, Some(item), write.listing));
         }
 
-        if updated > 0 || created > 0 {
+        let stored: Vec<String> = con.smembers(item_listings_key(item)).await?;
+        let vanished: Vec<String> = stored
+            .into_iter()
+            .filter(|key| !seen.contains(key))
+            .collect();
+
+        if !vanished.is_empty() {
+            let removed: Vec<String> = self
+                .remove_vanished_script
+                .key(item_listings_key(item))
+                .key(vanished)
+                .arg(created_at)
+                .invoke_async(&mut con)
+                .await?;
+
+            sync.removed = removed.len();
+            changes.extend(
+                removed
+                    .iter()
+                    .map(|key| ListingChange::delete(key, Some(item))),
+            );
+        }
+
+        sync.excluded = exclusions.total() as usize;
+
+        if sync.has_changes() {
             info!(
-                "Snapshot - Updated: {}, Created: {}, Ignored: {}",
-                updated,
-                created,
-                listing_len - updated - created
+                "Snapshot {} - Created: {}, Updated: {}, Removed: {}, Unchanged: {}, Outdated: {}, Excluded: {}",
+                item,
+                sync.created,
+                sync.updated,
+                sync.removed,
+                sync.unchanged,
+                sync.outdated,
+                sync.excluded
             );
         }
-        Ok(())
+
+        if exclusions.total() > 0 {
+            Self::record_exclusions(&mut con, &exclusions).await?;
+        }
+
+        self.publish_listing_changes(changes).await;
+
+        debug!(
+            "Wrote snapshot {} with {} listings in {:?}",
+            item,
+            seen.len(),
+            started.elapsed()
+        );
+
+        Ok(sync)
     }
 
     /// Get all the listings for a given item defindex
@@ -315,6 +832,7 @@ impl Database {
         };
 
         let mut listings = Vec::new();
+        let mut changes = Vec::new();
 
         for key in keys {
             let value: String = match con.get(&key).await {
@@ -335,11 +853,644 @@ impl Database {
                 // I have no clue what those args are
                 con.del::<&str, bool>(&key).await.unwrap();
                 info!("Deleted listing with id {:?}, reason: too old", listing.id);
+                changes.push(ListingChange::delete(&key, None));
             }
 
             listings.push(listing);
         }
 
+        self.publish_listing_changes(changes).await;
+
+        Ok(listings)
+    }
+
+    /// Get all the stored listings of the given item name
+    ///
+    /// Keys of listings that don't exist anymore (e.g. removed by `scan_for_old_listings`)
+    /// are removed from the item index on the way
+    pub async fn get_listings_for_item(
+        &self,
+        item: &str,
+    ) -> Result<Vec<UniversalListing>, RedisError> {
+        let mut conn = self.conn.clone();
+        let index_key = item_listings_key(item);
+
+        let keys: Vec<String> = conn.smembers(&index_key).await?;
+        let mut listings = Vec::with_capacity(keys.len());
+
+        for key in keys {
+            let value: Option<String> = conn.get(&key).await?;
+
+            let Some(value) = value else {
+                conn.srem::<_, _, ()>(&index_key, &key).await?;
+                continue;
+            };
+
+            match serde_json::from_str(&value) {
+                Ok(listing) => listings.push(listing),
+                Err(e) => {
+                    warn!("Failed to deserialize listing {}: {:?}", key, e);
+                }
+            }
+        }
+
         Ok(listings)
     }
+
+    /// Moves the listings stored under the old source specific ids to their canonical key
+    ///
+    /// Websocket listings used to be stored under the backpack.tf id and snapshot listings
+    /// under a derived one, so the same listing could exist twice. Duplicates are merged
+    /// by keeping the one that was bumped last, and every listing is added to the index of
+    /// its item, including the ones that were never indexed. Only runs once, returns the
+    /// number of listings that got moved
+    pub async fn migrate_listing_ids(&self) -> Result<usize, RedisError> {
+        let mut conn = self.conn.clone();
+
+        if conn.exists(LISTING_ID_MIGRATION_KEY).await? {
+            return Ok(0);
+        }
+
+        // listings stored before the item name was kept only know their item from the index
+        let mut indexed_items = HashMap::new();
+        let mut scan_conn = self.conn.clone();
+        let index_keys = {
+            let mut iter = scan_conn.scan_match(item_listings_key("*")).await?;
+            let mut index_keys: Vec<String> = Vec::new();
+            while let Some(index_key) = iter.next_item().await {
+                index_keys.push(index_key);
+            }
+            index_keys
+        };
+
+        for index_key in index_keys {
+            let item = index_key
+                .trim_start_matches(&item_listings_key(""))
+                .to_owned();
+            let keys: Vec<String> = conn.smembers(&index_key).await?;
+            for key in keys {
+                indexed_items.insert(key, item.clone());
+            }
+        }
+
+        let mut migrated = 0;
+        let mut listing_keys = scan_conn.scan_match::<_, String>("listing:*").await?;
+
+        while let Some(key) = listing_keys.next_item().await {
+            let value: Option<String> = conn.get(&key).await?;
+            let Some(value) = value else {
+                continue;
+            };
+
+            let mut listing: UniversalListing = match serde_json::from_str(&value) {
+                Ok(listing) => listing,
+                Err(e) => {
+                    warn!("Failed to deserialize listing {}: {:?}", key, e);
+                    continue;
+                }
+            };
+
+            let Some(item) = listing
+                .item
+                .name
+                .clone()
+                .or_else(|| indexed_items.get(&key).cloned())
+            else {
+                warn!("Can't migrate listing {}, its item is unknown", key);
+                continue;
+            };
+
+            let asset_id = listing.item.id.clone().map(|id| id.into());
+            let id = listing_id(&listing.intent, &listing.steamid, asset_id, &item);
+            let new_key = listing_key(listing.item.defindex, &id);
+            let index_key = item_listings_key(&item);
+
+            if new_key == key && listing.item.name.is_some() {
+                conn.sadd::<_, _, ()>(&index_key, &key).await?;
+                continue;
+            }
+
+            listing.id = Some(id);
+            listing.item.name = Some(item);
+
+            let existing: Option<String> = conn.get(&new_key).await?;
+            let keep_existing = existing
+                .and_then(|existing| serde_json::from_str::<UniversalListing>(&existing).ok())
+                .is_some_and(|existing| new_key != key && existing.bumped_at > listing.bumped_at);
+
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if !keep_existing {
+                pipe.set(&new_key, serde_json::to_string(&listing).unwrap())
+                    .ignore();
+            }
+            if new_key != key {
+                pipe.del(&key).ignore().srem(&index_key, &key).ignore();
+            }
+            pipe.sadd(&index_key, &new_key).ignore();
+            pipe.query_async::<_, ()>(&mut conn).await?;
+
+            migrated += 1;
+        }
+
+        conn.set::<_, _, ()>(LISTING_ID_MIGRATION_KEY, chrono::Utc::now().timestamp())
+            .await?;
+
+        if migrated > 0 {
+            info!("Migrated {} listings to their canonical id", migrated);
+        }
+
+        Ok(migrated)
+    }
+
+    /// Get the last published price suggestion of the given item
+    pub async fn get_price(&self, item: &str) -> Result<Option<PriceSuggestion>, RedisError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.get(format!("price:{}", item)).await?;
+
+        Ok(value.and_then(|value| match serde_json::from_str(&value) {
+            Ok(price) => Some(price),
+            Err(e) => {
+                warn!("Failed to deserialize price of item {}: {:?}", item, e);
+                None
+            }
+        }))
+    }
+
+    /// Stores the price suggestion as the last published price of its item
+    pub async fn set_price(&self, price: &PriceSuggestion) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let value = serde_json::to_string(price).unwrap();
+
+        conn.set(format!("price:{}", price.item), value).await
+    }
+
+    /// Get the published price changes between the unix timestamps, oldest first
+    ///
+    /// This reads the price stream, so changes older than what `REDIS_STREAM_MAXLEN` keeps are gone
+    pub async fn get_price_history(
+        &self,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<PriceChange>, RedisError> {
+        let mut conn = self.conn.clone();
+
+        // stream ids start with the millisecond timestamp of the entry
+        let millis = |time: i64| {
+            time.checked_mul(1000)
+                .ok_or_else(|| RedisError::from((ErrorKind::ClientError, "Time out of range")))
+        };
+        let start = match from {
+            Some(from) => millis(from)?.max(0).to_string(),
+            None => "-".to_owned(),
+        };
+        let end = match to {
+            Some(to) => (millis(to)?.max(0) + 999).to_string(),
+            None => "+".to_owned(),
+        };
+
+        let entries: Vec<(String, HashMap<String, String>)> = redis::cmd("XRANGE")
+            .arg(PRICES_STREAM)
+            .arg(start)
+            .arg(end)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(id, fields)| {
+                let data = fields.get("data")?;
+                match serde_json::from_str(data) {
+                    Ok(change) => Some(change),
+                    Err(e) => {
+                        warn!("Failed to deserialize price change {}: {:?}", id, e);
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Appends a webhook delivery that failed all its retries to the dead letter list
+    pub async fn push_webhook_dead_letter(&self, entry: &Value) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+
+        conn.rpush(WEBHOOK_DEAD_LETTER_KEY, entry.to_string()).await
+    }
+
+    /// Opens a pub/sub connection subscribed to the listing, price and override channels
+    pub async fn subscribe_changes(&self) -> Result<PubSub, RedisError> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+
+        pubsub.subscribe(LISTINGS_CHANNEL).await?;
+        pubsub.subscribe(PRICES_CHANNEL).await?;
+        pubsub.subscribe(OVERRIDES_CHANNEL).await?;
+
+        Ok(pubsub)
+    }
+
+    /// Adds the items to the tracked items, already tracked items get overwritten
+    pub async fn add_tracked_items(&self, items: &[TrackedItem]) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+
+        for item in items {
+            let value = serde_json::to_string(item).unwrap();
+            conn.hset::<_, _, _, ()>(TRACKED_ITEMS_KEY, &item.name, value)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get all the tracked items
+    pub async fn get_tracked_items(&self) -> Result<Vec<TrackedItem>, RedisError> {
+        let mut conn = self.conn.clone();
+        let values: HashMap<String, String> = conn.hgetall(TRACKED_ITEMS_KEY).await?;
+
+        Ok(values
+            .into_values()
+            .filter_map(|value| match serde_json::from_str(&value) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    warn!("Failed to deserialize tracked item: {:?}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Get the tracked item with the given name
+    pub async fn get_tracked_item(&self, name: &str) -> Result<Option<TrackedItem>, RedisError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.hget(TRACKED_ITEMS_KEY, name).await?;
+
+        Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    /// Finds the tracked item by its sku or name
+    pub async fn find_tracked_item(&self, sku: &str) -> Result<Option<TrackedItem>, RedisError> {
+        Ok(self
+            .get_tracked_items()
+            .await?
+            .into_iter()
+            .find(|item| item.sku.as_deref() == Some(sku) || item.name == sku))
+    }
+
+    /// Stores the pricelist settings of the item, replacing the previous ones
+    pub async fn set_pricelist_entry(
+        &self,
+        item: &str,
+        entry: &PricelistEntry,
+    ) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let value = serde_json::to_string(entry).unwrap();
+
+        conn.hset(PRICELIST_ENTRIES_KEY, item, value).await
+    }
+
+    /// Get the stored pricelist settings keyed by item name
+    pub async fn get_pricelist_entries(
+        &self,
+    ) -> Result<HashMap<String, PricelistEntry>, RedisError> {
+        let mut conn = self.conn.clone();
+        let values: HashMap<String, String> = conn.hgetall(PRICELIST_ENTRIES_KEY).await?;
+
+        Ok(values
+            .into_iter()
+            .filter_map(|(item, value)| match serde_json::from_str(&value) {
+                Ok(entry) => Some((item, entry)),
+                Err(e) => {
+                    warn!("Failed to deserialize pricelist entry of {}: {:?}", item, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Stores the override of the item, replacing the previous one, and publishes it on
+    /// the override channel
+    pub async fn set_override(&self, price: &PriceOverride) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let value = serde_json::to_string(price).unwrap();
+
+        conn.hset::<_, _, _, ()>(OVERRIDES_KEY, &price.item, value)
+            .await?;
+
+        self.publish_override_change(&OverrideChange::OverrideSet {
+            item: price.item.clone(),
+            price: price.clone(),
+            time: chrono::Utc::now().timestamp(),
+        })
+        .await
+    }
+
+    /// Get the override of the item, expired overrides are ignored
+    pub async fn get_override(&self, item: &str) -> Result<Option<PriceOverride>, RedisError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.hget(OVERRIDES_KEY, item).await?;
+
+        Ok(value
+            .and_then(|value| parse_override(item, &value))
+            .filter(|price| !price.is_expired(chrono::Utc::now().timestamp())))
+    }
+
+    /// Get all the overrides that didn't expire yet, sorted by item name
+    pub async fn get_overrides(&self) -> Result<Vec<PriceOverride>, RedisError> {
+        let mut conn = self.conn.clone();
+        let values: HashMap<String, String> = conn.hgetall(OVERRIDES_KEY).await?;
+        let now = chrono::Utc::now().timestamp();
+
+        let mut overrides: Vec<PriceOverride> = values
+            .iter()
+            .filter_map(|(item, value)| parse_override(item, value))
+            .filter(|price| !price.is_expired(now))
+            .collect();
+        overrides.sort_by(|a, b| a.item.cmp(&b.item));
+
+        Ok(overrides)
+    }
+
+    /// Removes the override of the item, returns whether there was one
+    pub async fn remove_override(&self, item: &str) -> Result<bool, RedisError> {
+        let mut conn = self.conn.clone();
+        let removed: usize = conn.hdel(OVERRIDES_KEY, item).await?;
+
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        self.publish_override_change(&OverrideChange::OverrideRemove {
+            item: item.to_owned(),
+            time: chrono::Utc::now().timestamp(),
+        })
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Removes the overrides that expired, so their removal gets published, returns the
+    /// removed overrides
+    pub async fn remove_expired_overrides(&self) -> Result<Vec<PriceOverride>, RedisError> {
+        let mut conn = self.conn.clone();
+        let values: HashMap<String, String> = conn.hgetall(OVERRIDES_KEY).await?;
+        let now = chrono::Utc::now().timestamp();
+
+        let mut removed = Vec::new();
+        for (item, value) in values {
+            let Some(price) = parse_override(&item, &value) else {
+                continue;
+            };
+
+            if price.is_expired(now) && self.remove_override(&item).await? {
+                info!("Override of item {} by {} expired", item, price.author);
+                removed.push(price);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Puts the suggestion into the review queue, replacing an older one of the item
+    pub async fn hold_price(&self, held: &HeldPrice) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let value = serde_json::to_string(held).unwrap();
+
+        conn.hset(REVIEW_QUEUE_KEY, &held.item, value).await
+    }
+
+    pub async fn get_held_price(&self, item: &str) -> Result<Option<HeldPrice>, RedisError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.hget(REVIEW_QUEUE_KEY, item).await?;
+
+        Ok(value.and_then(|value| match serde_json::from_str(&value) {
+            Ok(held) => Some(held),
+            Err(e) => {
+                warn!("Failed to deserialize held price of item {}: {:?}", item, e);
+                None
+            }
+        }))
+    }
+
+    /// Get the review queue, oldest first
+    pub async fn get_held_prices(&self) -> Result<Vec<HeldPrice>, RedisError> {
+        let mut conn = self.conn.clone();
+        let values: HashMap<String, String> = conn.hgetall(REVIEW_QUEUE_KEY).await?;
+
+        let mut held: Vec<HeldPrice> = values
+            .into_iter()
+            .filter_map(|(item, value)| match serde_json::from_str(&value) {
+                Ok(held) => Some(held),
+                Err(e) => {
+                    warn!("Failed to deserialize held price of item {}: {:?}", item, e);
+                    None
+                }
+            })
+            .collect();
+        held.sort_by_key(|held: &HeldPrice| held.time);
+
+        Ok(held)
+    }
+
+    /// Removes the item from the review queue, returns whether it was in there
+    pub async fn remove_held_price(&self, item: &str) -> Result<bool, RedisError> {
+        let mut conn = self.conn.clone();
+        let removed: usize = conn.hdel(REVIEW_QUEUE_KEY, item).await?;
+
+        Ok(removed > 0)
+    }
+
+    /// Trips the circuit breaker, returns false if it was already tripped
+    pub async fn trip_breaker(&self, trip: &BreakerTrip) -> Result<bool, RedisError> {
+        let mut conn = self.conn.clone();
+        let value = serde_json::to_string(trip).unwrap();
+
+        conn.set_nx(BREAKER_KEY, value).await
+    }
+
+    pub async fn get_breaker(&self) -> Result<Option<BreakerTrip>, RedisError> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.get(BREAKER_KEY).await?;
+
+        Ok(value.and_then(|value| match serde_json::from_str(&value) {
+            Ok(trip) => Some(trip),
+            Err(e) => {
+                warn!("Failed to deserialize the circuit breaker: {:?}", e);
+                None
+            }
+        }))
+    }
+
+    /// Resets the circuit breaker, returns whether it was tripped
+    pub async fn reset_breaker(&self) -> Result<bool, RedisError> {
+        let mut conn = self.conn.clone();
+        let removed: usize = conn.del(BREAKER_KEY).await?;
+
+        Ok(removed > 0)
+    }
+
+    async fn publish_override_change(&self, change: &OverrideChange) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let payload = serde_json::to_string(change).unwrap();
+
+        conn.publish(OVERRIDES_CHANNEL, payload).await
+    }
+
+    /// Adds the number of received websocket events per event type to the counters
+    pub async fn record_event_counts(
+        &self,
+        counts: &HashMap<String, u64>,
+    ) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+
+        for (event_type, count) in counts {
+            pipe.hincr(EVENT_STATS_KEY, event_type, *count).ignore();
+        }
+
+        pipe.query_async(&mut conn).await
+    }
+
+    /// Get the number of received websocket events per event type
+    pub async fn get_event_counts(&self) -> Result<HashMap<String, u64>, RedisError> {
+        let mut conn = self.conn.clone();
+        conn.hgetall(EVENT_STATS_KEY).await
+    }
+
+    /// Stores websocket events that couldn't be decoded for later inspection,
+    /// only the latest `EVENT_DEAD_LETTER_LIMIT` entries are kept
+    pub async fn push_event_dead_letters(&self, entries: &[Value]) -> Result<(), RedisError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn.clone();
+        let values: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+
+        redis::pipe()
+            .rpush(EVENT_DEAD_LETTER_KEY, values)
+            .ignore()
+            .ltrim(EVENT_DEAD_LETTER_KEY, -EVENT_DEAD_LETTER_LIMIT, -1)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    /// Get the stored websocket events that couldn't be decoded
+    pub async fn get_event_dead_letters(&self) -> Result<Vec<String>, RedisError> {
+        let mut conn = self.conn.clone();
+        conn.lrange(EVENT_DEAD_LETTER_KEY, 0, -1).await
+    }
+
+    /// Remembers when the snapshot of the item got fetched, shared by all pricer processes
+    pub async fn set_snapshot_fetched_at(
+        &self,
+        item: &str,
+        fetched_at: i64,
+    ) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        conn.hset(SNAPSHOT_FETCHED_AT_KEY, item, fetched_at).await
+    }
+
+    /// Remembers the `created_at` of the last snapshot of the item
+    pub async fn set_snapshot_created_at(
+        &self,
+        item: &str,
+        created_at: u32,
+    ) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        conn.hset(SNAPSHOT_CREATED_AT_KEY, item, created_at).await
+    }
+
+    pub async fn get_snapshot_freshness(
+        &self,
+        item: &str,
+    ) -> Result<SnapshotFreshness, RedisError> {
+        let mut conn = self.conn.clone();
+        let (fetched_at, created_at): (Option<i64>, Option<u32>) = redis::pipe()
+            .hget(SNAPSHOT_FETCHED_AT_KEY, item)
+            .hget(SNAPSHOT_CREATED_AT_KEY, item)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(SnapshotFreshness {
+            item: item.to_owned(),
+            fetched_at,
+            created_at,
+        })
+    }
+
+    /// Get the snapshot freshness of every item that was fetched at least once
+    pub async fn get_snapshot_freshnesses(&self) -> Result<Vec<SnapshotFreshness>, RedisError> {
+        let mut conn = self.conn.clone();
+        let (fetched_at, mut created_at): (HashMap<String, i64>, HashMap<String, u32>) =
+            redis::pipe()
+                .hgetall(SNAPSHOT_FETCHED_AT_KEY)
+                .hgetall(SNAPSHOT_CREATED_AT_KEY)
+                .query_async(&mut conn)
+                .await?;
+
+        let mut freshnesses: Vec<SnapshotFreshness> = fetched_at
+            .into_iter()
+            .map(|(item, fetched_at)| SnapshotFreshness {
+                created_at: created_at.remove(&item),
+                fetched_at: Some(fetched_at),
+                item,
+            })
+            .collect();
+        freshnesses.sort_by(|a, b| a.item.cmp(&b.item));
+
+        Ok(freshnesses)
+    }
+
+    /// Marks the instance as alive and forgets the instances that missed their heartbeats
+    /// for longer than `timeout` seconds
+    ///
+    /// Returns the ids of all the live instances
+    pub async fn heartbeat(&self, instance: &str, timeout: u64) -> Result<Vec<String>, RedisError> {
+        let mut conn = self.conn.clone();
+        let now = chrono::Utc::now().timestamp();
+
+        let (instances,): (Vec<String>,) = redis::pipe()
+            .atomic()
+            .zadd(CLUSTER_INSTANCES_KEY, instance, now)
+            .ignore()
+            .zrembyscore(CLUSTER_INSTANCES_KEY, "-inf", now - timeout as i64)
+            .ignore()
+            .zrange(CLUSTER_INSTANCES_KEY, 0, -1)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(instances)
+    }
+
+    /// Takes the lease for `ttl` milliseconds or renews it if the owner already holds it
+    ///
+    /// Returns `false` if another owner holds the lease
+    pub async fn acquire_lease(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl: u64,
+    ) -> Result<bool, RedisError> {
+        let mut conn = self.conn.clone();
+        let acquired: i64 = self
+            .acquire_lease_script
+            .key(key)
+            .arg(owner)
+            .arg(ttl)
+            .invoke_async(&mut conn)
+            .await?;
+
+        Ok(acquired == 1)
+    }
+
+    /// Gives up the lease, does nothing if another owner holds it by now
+    pub async fn release_lease(&self, key: &str, owner: &str) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        self.release_lease_script
+            .key(key)
+            .arg(owner)
+            .invoke_async(&mut conn)
+            .await
+    }
 }
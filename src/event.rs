@@ -1,6 +1,8 @@
 use super::types::{Listing, StrIntValue};
+use chrono::Utc;
 use log::info;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "event", content = "payload")]
@@ -9,6 +11,80 @@ pub enum Event {
     ListingUpdate(EventListing),
     #[serde(rename = "listing-delete")]
     ListingDelete(EventListingDeletion),
+    /// Any event type we don't handle, only the name of the type is kept
+    #[serde(skip)]
+    Unknown(String),
+}
+
+impl Event {
+    /// Name of the event type as sent by backpack.tf
+    pub fn event_type(&self) -> &str {
+        match self {
+            Event::ListingUpdate(_) => "listing-update",
+            Event::ListingDelete(_) => "listing-delete",
+            Event::Unknown(event_type) => event_type,
+        }
+    }
+
+    /// Decodes a single raw event, event types we don't know become `Event::Unknown`
+    ///
+    /// Only events without a type or known events with an unexpected payload fail
+    pub fn decode(raw: &Value) -> Result<Event, String> {
+        let Some(event_type) = raw.get("event").and_then(|event| event.as_str()) else {
+            return Err("event has no type".to_owned());
+        };
+
+        match event_type {
+            "listing-update" | "listing-delete" => {
+                Event::deserialize(raw).map_err(|e| e.to_string())
+            }
+            _ => Ok(Event::Unknown(event_type.to_owned())),
+        }
+    }
+}
+
+/// The events of one websocket frame
+#[derive(Debug, Default)]
+pub struct DecodedFrame {
+    pub events: Vec<Event>,
+    /// Raw events that couldn't be decoded, ready to be stored as dead letters
+    pub dead_letters: Vec<Value>,
+}
+
+impl DecodedFrame {
+    /// Decodes every event of the frame on its own so a single bad event
+    /// doesn't take the whole batch down
+    pub fn decode(frame: &str) -> Self {
+        let mut decoded = Self::default();
+
+        let raw_events = match serde_json::from_str::<Value>(frame) {
+            Ok(Value::Array(raw_events)) => raw_events,
+            Ok(raw_event) => vec![raw_event],
+            Err(e) => {
+                decoded
+                    .dead_letters
+                    .push(dead_letter(Value::String(frame.to_owned()), &e.to_string()));
+                return decoded;
+            }
+        };
+
+        for raw_event in raw_events {
+            match Event::decode(&raw_event) {
+                Ok(event) => decoded.events.push(event),
+                Err(e) => decoded.dead_letters.push(dead_letter(raw_event, &e)),
+            }
+        }
+
+        decoded
+    }
+}
+
+fn dead_letter(raw: Value, error: &str) -> Value {
+    json!({
+        "raw": raw,
+        "error": error,
+        "received_at": Utc::now().timestamp(),
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -39,9 +115,51 @@ pub struct ListingValue {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EventListingDeletion {
     pub id: String,
+    pub steamid: Option<String>,
+    pub intent: Option<String>,
     pub item: EventItem,
 }
 
+impl EventListingDeletion {
+    /// Derives the canonical id of the deleted listing from its intent and item, see
+    /// `listing_id`
+    ///
+    /// Sell listings only need the asset id, buy listings the steamid as well. Deletions
+    /// that don't carry enough for either keep the backpack.tf id
+    pub fn canonical_id(&self) -> String {
+        let asset_id: u64 = self.item.id.clone().into();
+
+        match (self.intent.as_deref(), self.steamid.as_deref()) {
+            (Some("sell"), steamid) if asset_id != 0 => listing_id(
+                "sell",
+                steamid.unwrap_or_default(),
+                Some(asset_id),
+                &self.item.name,
+            ),
+            (Some(intent), Some(steamid)) => {
+                listing_id(intent, steamid, Some(asset_id), &self.item.name)
+            }
+            _ => self.id.clone(),
+        }
+    }
+}
+
+/// Derives the id a listing is stored under, the same way for snapshots and websocket events
+///
+/// Sell listings are identified by their asset id, buy listings by the steamid of the
+/// buyer and the item name since there is only one buy listing per user and item
+pub fn listing_id(intent: &str, steamid: &str, asset_id: Option<u64>, item_name: &str) -> String {
+    match asset_id {
+        Some(asset_id) if intent == "sell" && asset_id != 0 => format!("440_{}", asset_id),
+        _ => format!("440_{}_{:x}", steamid, md5::compute(item_name)),
+    }
+}
+
+/// Key of the listing with the given id in the database
+pub fn listing_key(defindex: u32, id: &str) -> String {
+    format!("listing:{}:{}", defindex, id)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BPCurrencies {
     pub metal: Option<f32>,
@@ -70,11 +188,26 @@ pub struct UniversalListing {
     // TODO: user agent, item, timestamp?
 }
 
+impl UniversalListing {
+    /// Key the listing is stored under in the database
+    pub fn key(&self) -> String {
+        listing_key(self.item.defindex, self.id.as_deref().unwrap_or_default())
+    }
+}
+
+/// The item name of snapshot listings has to be set before the conversion,
+/// backpack.tf only sends it as the `sku` of the whole snapshot
 impl From<Listing> for UniversalListing {
     fn from(listing: Listing) -> Self {
         //  info!("listing item id: {:?}", listing.item.id);
+        let name = listing.item.name.unwrap_or_default();
         Self {
-            id: None,
+            id: Some(listing_id(
+                &listing.intent,
+                &listing.steamid,
+                listing.item.id,
+                &name,
+            )),
             steamid: listing.steamid,
             details: Some(listing.details),
             intent: listing.intent,
@@ -82,6 +215,7 @@ impl From<Listing> for UniversalListing {
             bumped_at: listing.bump,
             item: UniversalItem {
                 id: Some(StrIntValue::Int(listing.item.id.unwrap_or(0))),
+                name: Some(name),
                 defindex: listing.item.defindex as u32,
             },
         }
@@ -91,15 +225,22 @@ impl From<Listing> for UniversalListing {
 impl From<EventListing> for UniversalListing {
     fn from(event_listing: EventListing) -> Self {
         //  info!("eventlisting item id: {:?}", event_listing.item.id);
+        let asset_id: u64 = event_listing.item.id.into();
         Self {
-            id: Some(event_listing.id),
+            id: Some(listing_id(
+                &event_listing.intent,
+                &event_listing.steamid,
+                Some(asset_id),
+                &event_listing.item.name,
+            )),
             steamid: event_listing.steamid,
             details: event_listing.details,
             intent: event_listing.intent,
             price: event_listing.value.raw,
             bumped_at: event_listing.bumped_at,
             item: UniversalItem {
-                id: Some(StrIntValue::Int(event_listing.item.id.into())),
+                id: Some(StrIntValue::Int(asset_id)),
+                name: Some(event_listing.item.name),
                 defindex: event_listing.item.defindex,
             },
         }
@@ -111,5 +252,8 @@ pub struct UniversalItem {
     pub id: Option<StrIntValue>,
     // TODO: check if this is relevant
     //pub original_id: Option<StrIntValue>,
+    /// Name of the tracked item, not set for listings stored before it was introduced
+    #[serde(default)]
+    pub name: Option<String>,
     pub defindex: u32,
 }
@@ -0,0 +1,499 @@
+use std::{io::Write, sync::Arc};
+
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, FloatType, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use redis::RedisError;
+use serde_json::Value;
+
+use crate::{db::Database, event::UniversalListing, items::TrackedItem};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [
+        ExportFormat::Csv,
+        ExportFormat::Jsonl,
+        ExportFormat::Parquet,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    /// The sku isn't tracked
+    UnknownSku(String),
+    Redis(RedisError),
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Parquet(ParquetError),
+}
+
+impl From<RedisError> for ExportError {
+    fn from(e: RedisError) -> Self {
+        ExportError::Redis(e)
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        ExportError::Csv(e)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    /// UTF-8 string, `BYTE_ARRAY (UTF8)` in parquet
+    Text,
+    /// `INT64` in parquet
+    Integer,
+    /// `FLOAT` in parquet
+    Float,
+}
+
+/// One column of an export, the order of the columns is the order in every format
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+    pub nullable: bool,
+}
+
+impl Column {
+    const fn new(name: &'static str, kind: ColumnType, nullable: bool) -> Self {
+        Self {
+            name,
+            kind,
+            nullable,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Integer(i64),
+    Float(f32),
+    Null,
+}
+
+impl Cell {
+    fn to_csv(&self) -> String {
+        match self {
+            Cell::Text(text) => text.clone(),
+            Cell::Integer(integer) => integer.to_string(),
+            Cell::Float(float) => float.to_string(),
+            Cell::Null => String::new(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Cell::Text(text) => Value::from(text.as_str()),
+            Cell::Integer(integer) => Value::from(*integer),
+            // through the shortest decimal form, widening to f64 would add float noise
+            Cell::Float(float) => Value::from(float.to_string().parse::<f64>().unwrap()),
+            Cell::Null => Value::Null,
+        }
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Cell::Null, Into::into)
+    }
+}
+
+impl From<String> for Cell {
+    fn from(value: String) -> Self {
+        Cell::Text(value)
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(value: i64) -> Self {
+        Cell::Integer(value)
+    }
+}
+
+impl From<f32> for Cell {
+    fn from(value: f32) -> Self {
+        Cell::Float(value)
+    }
+}
+
+/// A row of an export, `cells` returns one cell per column in the order of `COLUMNS`
+pub trait ExportRow {
+    /// Name of the parquet schema
+    const NAME: &'static str;
+    const COLUMNS: &'static [Column];
+
+    fn cells(&self) -> Vec<Cell>;
+}
+
+/// A stored listing, see the README for the meaning of the columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingRow {
+    pub sku: String,
+    pub item: String,
+    pub key: String,
+    pub id: Option<String>,
+    pub intent: String,
+    pub steamid: String,
+    pub price: f32,
+    pub bumped_at: i64,
+    pub asset_id: Option<i64>,
+    pub defindex: i64,
+    pub details: Option<String>,
+}
+
+impl ListingRow {
+    pub fn new(item: &TrackedItem, listing: UniversalListing) -> Self {
+        Self {
+            sku: item.sku_or_name().to_owned(),
+            item: item.name.clone(),
+            key: listing.key(),
+            asset_id: listing
+                .item
+                .id
+                .map(|id| Into::<u64>::into(id) as i64)
+                .filter(|id| *id != 0),
+            defindex: listing.item.defindex as i64,
+            id: listing.id,
+            intent: listing.intent,
+            steamid: listing.steamid,
+            price: listing.price,
+            bumped_at: listing.bumped_at as i64,
+            details: listing.details.filter(|details| !details.is_empty()),
+        }
+    }
+}
+
+impl ExportRow for ListingRow {
+    const NAME: &'static str = "listing";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("sku", ColumnType::Text, false),
+        Column::new("item", ColumnType::Text, false),
+        Column::new("key", ColumnType::Text, false),
+        Column::new("id", ColumnType::Text, true),
+        Column::new("intent", ColumnType::Text, false),
+        Column::new("steamid", ColumnType::Text, false),
+        Column::new("price", ColumnType::Float, false),
+        Column::new("bumped_at", ColumnType::Integer, false),
+        Column::new("asset_id", ColumnType::Integer, true),
+        Column::new("defindex", ColumnType::Integer, false),
+        Column::new("details", ColumnType::Text, true),
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.sku.clone().into(),
+            self.item.clone().into(),
+            self.key.clone().into(),
+            self.id.clone().into(),
+            self.intent.clone().into(),
+            self.steamid.clone().into(),
+            self.price.into(),
+            self.bumped_at.into(),
+            self.asset_id.into(),
+            self.defindex.into(),
+            self.details.clone().into(),
+        ]
+    }
+}
+
+/// A published price suggestion, see the README for the meaning of the columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceRow {
+    pub sku: String,
+    pub item: String,
+    pub time: i64,
+    pub buy: Option<f32>,
+    pub sell: Option<f32>,
+    pub buy_listings: i64,
+    pub sell_listings: i64,
+}
+
+impl ExportRow for PriceRow {
+    const NAME: &'static str = "price";
+    const COLUMNS: &'static [Column] = &[
+        Column::new("sku", ColumnType::Text, false),
+        Column::new("item", ColumnType::Text, false),
+        Column::new("time", ColumnType::Integer, false),
+        Column::new("buy", ColumnType::Float, true),
+        Column::new("sell", ColumnType::Float, true),
+        Column::new("buy_listings", ColumnType::Integer, false),
+        Column::new("sell_listings", ColumnType::Integer, false),
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.sku.clone().into(),
+            self.item.clone().into(),
+            self.time.into(),
+            self.buy.into(),
+            self.sell.into(),
+            self.buy_listings.into(),
+            self.sell_listings.into(),
+        ]
+    }
+}
+
+/// Looks up the tracked items by sku or name, no skus select all the tracked items
+pub async fn select_items(db: &Database, skus: &[String]) -> Result<Vec<TrackedItem>, ExportError> {
+    let mut items = db.get_tracked_items().await?;
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if skus.is_empty() {
+        return Ok(items);
+    }
+
+    skus.iter()
+        .map(|sku| {
+            items
+                .iter()
+                .find(|item| item.sku.as_deref() == Some(sku) || &item.name == sku)
+                .cloned()
+                .ok_or_else(|| ExportError::UnknownSku(sku.clone()))
+        })
+        .collect()
+}
+
+/// The stored listings of the items that were bumped between the unix timestamps
+pub async fn listing_rows(
+    db: &Database,
+    items: &[TrackedItem],
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<ListingRow>, ExportError> {
+    let mut rows = Vec::new();
+
+    for item in items {
+        let mut listings = db.get_listings_for_item(&item.name).await?;
+        listings.sort_by(|a, b| a.bumped_at.cmp(&b.bumped_at).then(a.key().cmp(&b.key())));
+
+        rows.extend(
+            listings
+                .into_iter()
+                .filter(|listing| in_range(listing.bumped_at as i64, from, to))
+                .map(|listing| ListingRow::new(item, listing)),
+        );
+    }
+
+    Ok(rows)
+}
+
+/// The prices of the items that were published between the unix timestamps, oldest first
+pub async fn price_rows(
+    db: &Database,
+    items: &[TrackedItem],
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Vec<PriceRow>, ExportError> {
+    let history = db.get_price_history(from, to).await?;
+
+    Ok(history
+        .into_iter()
+        .filter_map(|change| {
+            let item = items.iter().find(|item| item.name == change.item)?;
+            let price = change.new;
+
+            Some(PriceRow {
+                sku: item.sku_or_name().to_owned(),
+                item: item.name.clone(),
+                time: price.time,
+                buy: price.buy,
+                sell: price.sell,
+                buy_listings: price.buy_listings as i64,
+                sell_listings: price.sell_listings as i64,
+            })
+        })
+        .filter(|row| in_range(row.time, from, to))
+        .collect())
+}
+
+fn in_range(time: i64, from: Option<i64>, to: Option<i64>) -> bool {
+    from.is_none_or(|from| time >= from) && to.is_none_or(|to| time <= to)
+}
+
+/// Writes the rows in the given format
+pub fn write_rows<R: ExportRow, W: Write + Send>(
+    rows: &[R],
+    format: ExportFormat,
+    writer: W,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::Csv => write_csv(rows, writer),
+        ExportFormat::Jsonl => write_jsonl(rows, writer),
+        ExportFormat::Parquet => write_parquet(rows, writer),
+    }
+}
+
+fn write_csv<R: ExportRow, W: Write>(rows: &[R], writer: W) -> Result<(), ExportError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(R::COLUMNS.iter().map(|column| column.name))?;
+
+    for row in rows {
+        writer.write_record(row.cells().iter().map(Cell::to_csv))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_jsonl<R: ExportRow, W: Write>(rows: &[R], mut writer: W) -> Result<(), ExportError> {
+    for row in rows {
+        // written by hand to keep the keys in column order
+        let fields: Vec<String> = R::COLUMNS
+            .iter()
+            .zip(row.cells())
+            .map(|(column, cell)| format!("{}:{}", Value::from(column.name), cell.to_json()))
+            .collect();
+
+        writeln!(writer, "{{{}}}", fields.join(","))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_parquet<R: ExportRow, W: Write + Send>(rows: &[R], writer: W) -> Result<(), ExportError> {
+    let fields: Vec<String> = R::COLUMNS
+        .iter()
+        .map(|column| {
+            let repetition = if column.nullable {
+                "OPTIONAL"
+            } else {
+                "REQUIRED"
+            };
+            let kind = match column.kind {
+                ColumnType::Text => "BYTE_ARRAY",
+                ColumnType::Integer => "INT64",
+                ColumnType::Float => "FLOAT",
+            };
+            let annotation = match column.kind {
+                ColumnType::Text => " (UTF8)",
+                _ => "",
+            };
+
+            format!("{} {} {}{};", repetition, kind, column.name, annotation)
+        })
+        .collect();
+    let schema = parse_message_type(&format!("message {} {{ {} }}", R::NAME, fields.join(" ")))?;
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut file = SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties))?;
+
+    if !rows.is_empty() {
+        let cells: Vec<Vec<Cell>> = rows.iter().map(|row| row.cells()).collect();
+        let mut row_group = file.next_row_group()?;
+        let mut index = 0;
+
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let column = &R::COLUMNS[index];
+            let column_cells: Vec<&Cell> = cells.iter().map(|row| &row[index]).collect();
+
+            // nulls are left out of the values and marked by a definition level of 0
+            let definition_levels: Vec<i16> = column_cells
+                .iter()
+                .map(|cell| i16::from(**cell != Cell::Null))
+                .collect();
+            let definition_levels = column.nullable.then_some(definition_levels.as_slice());
+
+            match column.kind {
+                ColumnType::Text => {
+                    let values: Vec<ByteArray> = column_cells
+                        .iter()
+                        .filter_map(|cell| match cell {
+                            Cell::Text(text) => Some(ByteArray::from(text.as_bytes().to_vec())),
+                            _ => None,
+                        })
+                        .collect();
+                    column_writer.typed::<ByteArrayType>().write_batch(
+                        &values,
+                        definition_levels,
+                        None,
+                    )?;
+                }
+                ColumnType::Integer => {
+                    let values: Vec<i64> = column_cells
+                        .iter()
+                        .filter_map(|cell| match cell {
+                            Cell::Integer(integer) => Some(*integer),
+                            _ => None,
+                        })
+                        .collect();
+                    column_writer.typed::<Int64Type>().write_batch(
+                        &values,
+                        definition_levels,
+                        None,
+                    )?;
+                }
+                ColumnType::Float => {
+                    let values: Vec<f32> = column_cells
+                        .iter()
+                        .filter_map(|cell| match cell {
+                            Cell::Float(float) => Some(*float),
+                            _ => None,
+                        })
+                        .collect();
+                    column_writer.typed::<FloatType>().write_batch(
+                        &values,
+                        definition_levels,
+                        None,
+                    )?;
+                }
+            }
+
+            column_writer.close()?;
+            index += 1;
+        }
+
+        row_group.close()?;
+    }
+
+    file.close()?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use log::{error, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    db::Database,
+    event::UniversalListing,
+    items::TrackedItem,
+    notifier::{PriceChange, PRICE_CHANGE_EVENT},
+    pricing::{get_item_price, get_key_price, ItemPrice, PriceConfig},
+    publish::{ListingChange, OverrideChange, LISTINGS_CHANNEL, OVERRIDES_CHANNEL, PRICES_CHANNEL},
+};
+
+/// How many messages a slow client can fall behind before it starts missing some
+const FEED_CAPACITY: usize = 1024;
+
+/// Message sent to the feed clients, the prices use the same shape as the
+/// tf2autobot price source socket
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum FeedMessage {
+    Price(ItemPrice),
+    ListingUpdate {
+        sku: String,
+        name: String,
+        key: String,
+        listing: UniversalListing,
+    },
+    ListingDelete {
+        sku: String,
+        name: String,
+        key: String,
+    },
+}
+
+/// A serialized feed message together with the item it belongs to
+#[derive(Debug, Clone)]
+pub struct FeedEvent {
+    pub item: String,
+    pub message: Arc<String>,
+    /// The serialized price of price messages, sent as is to the socket.io clients
+    pub price: Option<Arc<String>>,
+}
+
+/// Turns the listing and price changes published on redis into feed messages
+/// and broadcasts them to every connected client
+#[derive(Clone)]
+pub struct Feed {
+    db: Database,
+    config: PriceConfig,
+    sender: broadcast::Sender<FeedEvent>,
+}
+
+impl Feed {
+    pub fn new(db: Database, config: PriceConfig) -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+
+        Self { db, config, sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Forwards the changes from redis to the clients, reconnects if the
+    /// pub/sub connection gets lost
+    ///
+    /// Setting or removing an override sends the price the clients should use from then on
+    pub async fn run(&self) {
+        loop {
+            let pubsub = match self.db.subscribe_changes().await {
+                Ok(pubsub) => pubsub,
+                Err(e) => {
+                    error!("Failed to subscribe to the redis channels: {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            let mut messages = pubsub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                let payload: String = match msg.get_payload() {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("Failed to read pub/sub payload: {:?}", e);
+                        continue;
+                    }
+                };
+
+                let Some(event) = self.to_event(msg.get_channel_name(), &payload).await else {
+                    continue;
+                };
+
+                // sending only fails if no client is connected
+                let _ = self.sender.send(event);
+            }
+
+            warn!("Lost the redis pub/sub connection, reconnecting");
+        }
+    }
+
+    async fn to_event(&self, channel: &str, payload: &str) -> Option<FeedEvent> {
+        let (item, message) = match channel {
+            PRICES_CHANNEL => {
+                let change: PriceChange = serde_json::from_str(payload).ok()?;
+                // override changes reach the clients through the override channel
+                if change.event != PRICE_CHANGE_EVENT {
+                    return None;
+                }
+                let tracked = self.tracked_item(&change.item).await;
+
+                let key_price = match get_key_price(&self.db, &self.config).await {
+                    Ok(key_price) => key_price,
+                    Err(e) => {
+                        error!("Failed to get the key price: {:?}", e);
+                        return None;
+                    }
+                };
+
+                // the clients keep the overridden price until the override is removed
+                match self.db.get_override(&change.item).await {
+                    Ok(None) => {}
+                    Ok(Some(_)) => return None,
+                    Err(e) => {
+                        error!("Failed to get the override of {}: {:?}", change.item, e);
+                        return None;
+                    }
+                }
+
+                let price = ItemPrice::new(&tracked, &change.new, key_price)?;
+                (change.item, FeedMessage::Price(price))
+            }
+            OVERRIDES_CHANNEL => {
+                let change: OverrideChange = serde_json::from_str(payload).ok()?;
+                let tracked = self.tracked_item(change.item()).await;
+
+                // a removed override goes back to the suggestion
+                let price = match get_key_price(&self.db, &self.config).await {
+                    Ok(key_price) => {
+                        get_item_price(&self.db, &self.config, &tracked, key_price).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let price = match price {
+                    Ok(price) => price?,
+                    Err(e) => {
+                        error!("Failed to get the price of {}: {:?}", tracked.name, e);
+                        return None;
+                    }
+                };
+
+                (tracked.name, FeedMessage::Price(price))
+            }
+            LISTINGS_CHANNEL => match serde_json::from_str(payload).ok()? {
+                ListingChange::ListingUpdate {
+                    key, item, listing, ..
+                } => {
+                    let tracked = self.tracked_item(&item?).await;
+                    let message = FeedMessage::ListingUpdate {
+                        sku: tracked.sku_or_name().to_owned(),
+                        name: tracked.name.clone(),
+                        key,
+                        listing,
+                    };
+                    (tracked.name, message)
+                }
+                ListingChange::ListingDelete { key, item, .. } => {
+                    let tracked = self.tracked_item(&item?).await;
+                    let message = FeedMessage::ListingDelete {
+                        sku: tracked.sku_or_name().to_owned(),
+                        name: tracked.name.clone(),
+                        key,
+                    };
+                    (tracked.name, message)
+                }
+            },
+            _ => return None,
+        };
+
+        let price = match &message {
+            FeedMessage::Price(price) => Some(Arc::new(serde_json::to_string(price).unwrap())),
+            _ => None,
+        };
+
+        Some(FeedEvent {
+            item,
+            message: Arc::new(serde_json::to_string(&message).unwrap()),
+            price,
+        })
+    }
+
+    /// Looks up the tracked item, items that aren't tracked (anymore) only have a name
+    async fn tracked_item(&self, name: &str) -> TrackedItem {
+        match self.db.get_tracked_item(name).await {
+            Ok(Some(item)) => item,
+            Ok(None) => TrackedItem {
+                name: name.to_owned(),
+                sku: None,
+            },
+            Err(e) => {
+                warn!("Failed to get tracked item {}: {:?}", name, e);
+                TrackedItem {
+                    name: name.to_owned(),
+                    sku: None,
+                }
+            }
+        }
+    }
+}
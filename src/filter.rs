@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+/// Why a listing was kept out of the database
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExclusionReason {
+    /// The listing belongs to one of our own bots
+    Ignored,
+    /// The listing belongs to a known price manipulator
+    Blocked,
+}
+
+impl ExclusionReason {
+    /// Name of the field used for the exclusion counters in redis
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExclusionReason::Ignored => "ignored",
+            ExclusionReason::Blocked => "blocked",
+        }
+    }
+}
+
+/// Decides which listings should never make it into the database
+///
+/// Our own bots list the same items the pricer watches, so their listings would
+/// otherwise feed back into the suggestions.
+#[derive(Debug, Clone, Default)]
+pub struct ListingFilter {
+    ignored: HashSet<String>,
+    blocked: HashSet<String>,
+}
+
+impl ListingFilter {
+    pub fn new(ignored: HashSet<String>, blocked: HashSet<String>) -> Self {
+        Self { ignored, blocked }
+    }
+
+    /// Reads the comma separated `IGNORED_STEAMIDS` and `BLOCKED_STEAMIDS` from the env,
+    /// both are optional
+    pub fn from_env() -> Self {
+        Self::new(
+            parse_steamids(std::env::var("IGNORED_STEAMIDS").ok()),
+            parse_steamids(std::env::var("BLOCKED_STEAMIDS").ok()),
+        )
+    }
+
+    /// Returns the reason the listing of the given steamid should be excluded, if any
+    pub fn check(&self, steamid: &str) -> Option<ExclusionReason> {
+        if self.ignored.contains(steamid) {
+            return Some(ExclusionReason::Ignored);
+        }
+
+        if self.blocked.contains(steamid) {
+            return Some(ExclusionReason::Blocked);
+        }
+
+        None
+    }
+}
+
+fn parse_steamids(value: Option<String>) -> HashSet<String> {
+    match value {
+        Some(value) => value
+            .split(',')
+            .map(|steamid| steamid.trim())
+            .filter(|steamid| !steamid.is_empty())
+            .map(|steamid| steamid.to_owned())
+            .collect(),
+        None => HashSet::new(),
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use redis::RedisError;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::Database, pricing::PriceSuggestion};
+
+/// How often the circuit breaker looks at the websocket event counters
+const BREAKER_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Window the deletions are counted in
+const DELETION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Why a suggestion didn't pass the guard
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum GuardReason {
+    /// The buy price is above the sell price
+    Crossed,
+    /// A side moved more than `PRICE_GUARD_MAX_CHANGE` percent from the published price
+    Change { percent: f32 },
+}
+
+/// What happens to suggestions that don't pass the guard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuardMode {
+    /// They wait in the review queue until they are approved or rejected
+    Hold,
+    /// They are dropped
+    Reject,
+}
+
+/// A suggestion waiting in the review queue
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HeldPrice {
+    pub item: String,
+    /// The published price at the time the suggestion was held
+    pub old: Option<PriceSuggestion>,
+    pub new: PriceSuggestion,
+    pub reason: GuardReason,
+    pub time: i64,
+}
+
+/// Checks every suggestion before it gets published
+#[derive(Debug, Clone, Copy)]
+pub struct PriceGuard {
+    /// Maximum change in percent, 0 disables the check
+    max_change: f32,
+    mode: GuardMode,
+}
+
+impl PriceGuard {
+    pub fn new(max_change: f32, mode: GuardMode) -> Self {
+        Self { max_change, mode }
+    }
+
+    pub fn from_env() -> Self {
+        let max_change = match std::env::var("PRICE_GUARD_MAX_CHANGE") {
+            Ok(max_change) => max_change
+                .parse()
+                .expect("PRICE_GUARD_MAX_CHANGE is not a number"),
+            Err(_) => 50.0,
+        };
+
+        let mode = match std::env::var("PRICE_GUARD_MODE").as_deref() {
+            Ok("hold") | Err(_) => GuardMode::Hold,
+            Ok("reject") => GuardMode::Reject,
+            Ok(mode) => panic!("PRICE_GUARD_MODE must be hold or reject, got {}", mode),
+        };
+
+        Self::new(max_change, mode)
+    }
+
+    pub fn mode(&self) -> GuardMode {
+        self.mode
+    }
+
+    /// Returns why the suggestion can't be published as it is
+    ///
+    /// Only sides that are priced before and after are compared, a side that appears or
+    /// disappears is normal for thinly traded items
+    pub fn check(
+        &self,
+        old: Option<&PriceSuggestion>,
+        new: &PriceSuggestion,
+    ) -> Option<GuardReason> {
+        if new.is_crossed() {
+            return Some(GuardReason::Crossed);
+        }
+
+        let percent = new.priced_change_from(old?);
+        if self.max_change > 0.0 && percent > self.max_change {
+            return Some(GuardReason::Change { percent });
+        }
+
+        None
+    }
+}
+
+/// Why the circuit breaker froze the prices
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakerReason {
+    /// No websocket events arrived for `BREAKER_WS_SILENCE` seconds, released
+    /// automatically once they arrive again
+    WebsocketOutage,
+    /// More than `BREAKER_MAX_DELETES` listings got deleted within a minute
+    MassDeletion,
+    Manual,
+}
+
+/// A tripped circuit breaker, no price gets published until it is reset
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BreakerTrip {
+    pub reason: BreakerReason,
+    pub message: String,
+    pub tripped_at: i64,
+}
+
+impl BreakerTrip {
+    pub fn new(reason: BreakerReason, message: String) -> Self {
+        Self {
+            reason,
+            message,
+            tripped_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Freezes all prices when the websocket goes quiet or a suspicious amount of listings
+/// gets deleted
+///
+/// Both are read from the websocket event counters, so only the instance that consumes
+/// the websocket should run the checks
+pub struct CircuitBreaker {
+    db: Database,
+    /// Seconds without websocket events before the breaker trips, 0 disables the check
+    ws_silence: u64,
+    /// Deletions per minute before the breaker trips, 0 disables the check
+    max_deletes: u64,
+    last_total: Option<u64>,
+    last_event_at: Instant,
+    deletions: VecDeque<(Instant, u64)>,
+}
+
+impl CircuitBreaker {
+    pub fn new(db: Database, ws_silence: u64, max_deletes: u64) -> Self {
+        Self {
+            db,
+            ws_silence,
+            max_deletes,
+            last_total: None,
+            last_event_at: Instant::now(),
+            deletions: VecDeque::new(),
+        }
+    }
+
+    pub fn from_env(db: Database) -> Self {
+        let ws_silence = match std::env::var("BREAKER_WS_SILENCE") {
+            Ok(silence) => silence.parse().expect("BREAKER_WS_SILENCE is not a number"),
+            Err(_) => 300,
+        };
+
+        let max_deletes = match std::env::var("BREAKER_MAX_DELETES") {
+            Ok(max_deletes) => max_deletes
+                .parse()
+                .expect("BREAKER_MAX_DELETES is not a number"),
+            Err(_) => 10000,
+        };
+
+        Self::new(db, ws_silence, max_deletes)
+    }
+
+    /// Checks the counters in a loop while `is_active` returns true, the samples start
+    /// over whenever it returns false
+    pub async fn run(mut self, is_active: impl Fn() -> bool) {
+        loop {
+            tokio::time::sleep(BREAKER_CHECK_INTERVAL).await;
+
+            if !is_active() {
+                self.reset_samples();
+                continue;
+            }
+
+            if let Err(e) = self.check().await {
+                error!("Failed to check the circuit breaker: {:?}", e);
+            }
+        }
+    }
+
+    fn reset_samples(&mut self) {
+        self.last_total = None;
+        self.last_event_at = Instant::now();
+        self.deletions.clear();
+    }
+
+    async fn check(&mut self) -> Result<(), RedisError> {
+        let counts = self.db.get_event_counts().await?;
+        let now = Instant::now();
+
+        let total: u64 = counts.values().sum();
+        let received = self.last_total.is_some_and(|last_total| total > last_total);
+        if received {
+            self.last_event_at = now;
+        }
+        self.last_total = Some(total);
+
+        let tripped = self.db.get_breaker().await?;
+        if received
+            && tripped.as_ref().map(|trip| trip.reason) == Some(BreakerReason::WebsocketOutage)
+        {
+            info!("Websocket events arrive again, releasing the circuit breaker");
+            self.db.reset_breaker().await?;
+        }
+
+        let silence = now.duration_since(self.last_event_at).as_secs();
+        if self.ws_silence > 0 && silence >= self.ws_silence && tripped.is_none() {
+            self.trip(
+                BreakerReason::WebsocketOutage,
+                format!("No websocket events for {} seconds", silence),
+            )
+            .await?;
+        }
+
+        let deleted = deletion_count(&counts);
+        self.deletions.push_back((now, deleted));
+        while self
+            .deletions
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > DELETION_WINDOW)
+        {
+            self.deletions.pop_front();
+        }
+
+        let window_deletes =
+            deleted.saturating_sub(self.deletions.front().map_or(deleted, |(_, count)| *count));
+        if self.max_deletes > 0 && window_deletes > self.max_deletes {
+            self.trip(
+                BreakerReason::MassDeletion,
+                format!("{} listings got deleted within a minute", window_deletes),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn trip(&self, reason: BreakerReason, message: String) -> Result<(), RedisError> {
+        if self
+            .db
+            .trip_breaker(&BreakerTrip::new(reason, message.clone()))
+            .await?
+        {
+            warn!("Circuit breaker tripped, prices are frozen: {}", message);
+        }
+
+        Ok(())
+    }
+}
+
+fn deletion_count(counts: &HashMap<String, u64>) -> u64 {
+    counts.get("listing-delete").copied().unwrap_or_default()
+}
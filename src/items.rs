@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// An item the pricer keeps track of
+///
+/// Listings are synced by the item name since that's what backpack.tf uses, the sku is
+/// only needed for the tf2autobot compatible outputs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrackedItem {
+    pub name: String,
+    pub sku: Option<String>,
+}
+
+impl TrackedItem {
+    /// Returns the sku of the item, falling back to the name if no sku is known
+    pub fn sku_or_name(&self) -> &str {
+        self.sku.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Parses the comma separated `ITEMS` value, every entry is either just the item name
+/// or `sku=name`, e.g. `5021;6=Mann Co. Supply Crate Key`
+pub fn parse_tracked_items(value: &str) -> Vec<TrackedItem> {
+    value
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((sku, name)) => TrackedItem {
+                name: name.trim().to_owned(),
+                sku: Some(sku.trim().to_owned()),
+            },
+            None => TrackedItem {
+                name: entry.to_owned(),
+                sku: None,
+            },
+        })
+        .collect()
+}
@@ -0,0 +1,69 @@
+//! Syncs backpack.tf listings into redis and turns them into price suggestions
+//!
+//! The listing and pricing types are always available, the rest is behind features:
+//!
+//! - `redis`: the `Database` store and the `BackpackTF` client that syncs into it, plus
+//!   clustering, webhooks, the price guard and tf2autobot pricelist imports
+//! - `server`: the HTTP API and the price feed, implies `redis`
+//! - `archive`: the raw event archive and backtesting, replay also needs `redis`
+//! - `export`: CSV, JSON Lines and Parquet exports of the stored listings and prices,
+//!   implies `redis`
+//!
+//! All of them are enabled by default. The types re-exported here are the stable API,
+//! the modules expose more but may change between versions
+
+#[cfg(feature = "server")]
+pub mod api;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "archive")]
+pub mod backtest;
+pub mod book;
+#[cfg(feature = "redis")]
+pub mod bptf;
+#[cfg(all(
+    feature = "redis",
+    feature = "server",
+    feature = "archive",
+    feature = "export"
+))]
+#[doc(hidden)]
+pub mod cli;
+#[cfg(feature = "redis")]
+pub mod cluster;
+#[cfg(feature = "redis")]
+pub mod db;
+pub mod event;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "server")]
+pub mod feed;
+pub mod filter;
+#[cfg(feature = "redis")]
+pub mod guard;
+pub mod items;
+pub mod margin;
+#[cfg(feature = "redis")]
+pub mod notifier;
+#[cfg(feature = "redis")]
+pub mod pricelist;
+pub mod pricing;
+pub mod publish;
+#[cfg(feature = "redis")]
+mod ratelimit;
+#[cfg(all(feature = "redis", feature = "archive"))]
+pub mod replay;
+#[cfg(feature = "server")]
+pub mod socketio;
+pub mod types;
+
+#[cfg(feature = "redis")]
+pub use bptf::BackpackTF;
+#[cfg(feature = "redis")]
+pub use db::{Database, SnapshotFreshness, SnapshotSync};
+pub use event::{listing_id, listing_key, Event, UniversalItem, UniversalListing};
+pub use filter::ListingFilter;
+pub use items::TrackedItem;
+pub use pricing::{ItemCurrencies, ItemPrice, PriceSuggestion};
+pub use publish::ListingChange;
+pub use types::{Listing, ListingResponse, PricingError};
@@ -0,0 +1,155 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tf2_price::{get_metal_float_from_weapons, ONE_SCRAP};
+
+use crate::pricing::{PriceSuggestion, KEY_ITEM};
+
+/// Which price moves when the spread is too small
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WidenDirection {
+    /// Lowers the buy price
+    Buy,
+    /// Raises the sell price
+    Sell,
+    /// Moves both by half the missing spread
+    Both,
+}
+
+/// Why the minimum margin couldn't be enforced
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnmetReason {
+    /// The buy price is above the sell price, that is left to the price guard
+    Crossed,
+    /// The buy price would drop to zero or below
+    BuyTooLow,
+}
+
+/// Outcome of the margin stage, stored with the suggestion
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum MarginStatus {
+    /// The spread was wide enough already
+    Met,
+    /// The spread got widened, `buy` and `sell` are the averages before
+    Widened { buy: f32, sell: f32 },
+    /// The spread is left as it is
+    Unmet { reason: UnmetReason },
+}
+
+/// Minimum profit between the buy and sell price of a suggestion, in scrap and in percent
+/// of the buy price, the bigger one applies
+#[derive(Debug, Clone, Copy)]
+pub struct MarginPolicy {
+    min_scrap: i64,
+    min_percent: f32,
+    direction: WidenDirection,
+}
+
+impl MarginPolicy {
+    pub fn new(min_scrap: i64, min_percent: f32, direction: WidenDirection) -> Self {
+        Self {
+            min_scrap,
+            min_percent,
+            direction,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let min_scrap = match std::env::var("MIN_MARGIN_SCRAP") {
+            Ok(min_scrap) => min_scrap.parse().expect("MIN_MARGIN_SCRAP is not a number"),
+            Err(_) => 0,
+        };
+
+        let min_percent = match std::env::var("MIN_MARGIN_PERCENT") {
+            Ok(min_percent) => min_percent
+                .parse()
+                .expect("MIN_MARGIN_PERCENT is not a number"),
+            Err(_) => 0.0,
+        };
+
+        let direction = match std::env::var("MARGIN_DIRECTION").as_deref() {
+            Ok("both") | Err(_) => WidenDirection::Both,
+            Ok("buy") => WidenDirection::Buy,
+            Ok("sell") => WidenDirection::Sell,
+            Ok(direction) => panic!(
+                "MARGIN_DIRECTION must be buy, sell or both, got {}",
+                direction
+            ),
+        };
+
+        Self::new(min_scrap, min_percent, direction)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.min_scrap > 0 || self.min_percent > 0.0
+    }
+
+    /// Minimum spread in refined for the buy price
+    fn min_spread(&self, buy: f32) -> f32 {
+        let scrap = get_metal_float_from_weapons(self.min_scrap * ONE_SCRAP);
+        scrap.max(buy * self.min_percent / 100.0)
+    }
+
+    /// Widens the spread of the suggestion until it has the minimum margin and stores the
+    /// outcome in `margin`
+    ///
+    /// Suggestions missing a side are left alone, there is no spread to enforce, and so is
+    /// the key's
+    pub fn apply(&self, suggestion: &mut PriceSuggestion) {
+        suggestion.margin = None;
+
+        // the key's sell price is the key price every other item is converted with
+        if !self.is_enabled() || suggestion.item == KEY_ITEM {
+            return;
+        }
+
+        let (Some(buy), Some(sell)) = (suggestion.buy, suggestion.sell) else {
+            return;
+        };
+
+        if buy > sell {
+            suggestion.margin = Some(MarginStatus::Unmet {
+                reason: UnmetReason::Crossed,
+            });
+            return;
+        }
+
+        let min_spread = self.min_spread(buy);
+        // the prices are rounded to scrap later, so tiny float errors don't count
+        let missing = min_spread - (sell - buy);
+        if missing <= 0.001 {
+            suggestion.margin = Some(MarginStatus::Met);
+            return;
+        }
+
+        let (new_buy, new_sell) = match self.direction {
+            WidenDirection::Buy => (
+                (sell - self.min_spread(0.0)).min(sell / (1.0 + self.min_percent / 100.0)),
+                sell,
+            ),
+            WidenDirection::Sell => (buy, buy + min_spread),
+            WidenDirection::Both => (buy - missing / 2.0, sell + missing / 2.0),
+        };
+
+        if new_buy <= 0.0 {
+            warn!(
+                "Can't keep the minimum margin of item {}, buy: {}, sell: {}",
+                suggestion.item, buy, sell
+            );
+            suggestion.margin = Some(MarginStatus::Unmet {
+                reason: UnmetReason::BuyTooLow,
+            });
+            return;
+        }
+
+        debug!(
+            "Widened the spread of item {} from {} - {} to {} - {}",
+            suggestion.item, buy, sell, new_buy, new_sell
+        );
+
+        suggestion.buy = Some(new_buy);
+        suggestion.sell = Some(new_sell);
+        suggestion.margin = Some(MarginStatus::Widened { buy, sell });
+    }
+}
@@ -0,0 +1,505 @@
+use std::{collections::HashSet, time::Duration};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use redis::RedisError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{
+    db::Database,
+    guard::{GuardMode, GuardReason, HeldPrice},
+    items::TrackedItem,
+    pricing::{get_key_price, ItemPrice, PriceConfig, PriceOverride, PriceSuggestion},
+};
+
+/// Header that carries the hex encoded HMAC-SHA256 of the request body
+const SIGNATURE_HEADER: &str = "X-Pricer-Signature";
+/// Most retries of a failed delivery, `WEBHOOK_MAX_RETRIES` is clamped to it
+const MAX_RETRIES: u32 = 20;
+/// The delay between retries doubles up to 2^6 = 64 seconds
+const MAX_RETRY_DELAY_SHIFT: u32 = 6;
+
+/// Event of a suggestion that moved beyond the threshold
+pub const PRICE_CHANGE_EVENT: &str = "price-change";
+/// Event of an override that got set, the override is the new price
+pub const OVERRIDE_SET_EVENT: &str = "override-set";
+/// Event of an override that got removed or expired, the suggestion is the new price
+pub const OVERRIDE_REMOVE_EVENT: &str = "override-remove";
+
+/// Payload that gets posted to the webhooks when the price of an item changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceChange {
+    pub event: String,
+    pub item: String,
+    pub old: Option<PriceSuggestion>,
+    /// The price in refined, overrides are converted with the key price
+    pub new: PriceSuggestion,
+    /// Whether the new price comes from an override
+    #[serde(default)]
+    pub overridden: bool,
+    /// The override in keys and metal, only set if `overridden` is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<ItemPrice>,
+    pub time: i64,
+}
+
+/// Recomputes the price of items after updates and publishes every price that moved
+/// more than the threshold on redis and to the configured webhooks
+///
+/// Suggestions get the minimum margin and go through the price guard first, prices of
+/// overridden items are stored but not published. Setting or removing an override
+/// publishes the price the item has from then on
+#[derive(Clone)]
+pub struct Notifier {
+    req_client: Client,
+    db: Database,
+    urls: Vec<String>,
+    secret: Option<String>,
+    /// Minimum change in percent that triggers a notification
+    threshold: f32,
+    max_retries: u32,
+    config: PriceConfig,
+}
+
+impl Notifier {
+    pub fn new(
+        db: Database,
+        urls: Vec<String>,
+        secret: Option<String>,
+        threshold: f32,
+        max_retries: u32,
+        config: PriceConfig,
+    ) -> Result<Self, reqwest::Error> {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self {
+            req_client: client,
+            db,
+            urls,
+            secret,
+            threshold,
+            max_retries,
+            config,
+        })
+    }
+
+    /// Creates the notifier from the env, webhooks are only used if `WEBHOOK_URLS` is set
+    pub fn from_env(db: Database) -> Result<Self, reqwest::Error> {
+        let urls: Vec<String> = std::env::var("WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|url| url.trim().to_owned())
+            .filter(|url| !url.is_empty())
+            .collect();
+
+        let threshold = match std::env::var("PRICE_CHANGE_THRESHOLD") {
+            Ok(threshold) => threshold
+                .parse()
+                .expect("PRICE_CHANGE_THRESHOLD is not a number"),
+            Err(_) => 5.0,
+        };
+
+        let max_retries = match std::env::var("WEBHOOK_MAX_RETRIES") {
+            Ok(retries) => retries
+                .parse()
+                .expect("WEBHOOK_MAX_RETRIES is not a number"),
+            Err(_) => 3,
+        };
+        if max_retries > MAX_RETRIES {
+            warn!(
+                "WEBHOOK_MAX_RETRIES is {}, only {} retries are made",
+                max_retries, MAX_RETRIES
+            );
+        }
+        let max_retries = max_retries.min(MAX_RETRIES);
+
+        Self::new(
+            db,
+            urls,
+            std::env::var("WEBHOOK_SECRET").ok(),
+            threshold,
+            max_retries,
+            PriceConfig::from_env(),
+        )
+    }
+
+    /// Recomputes the price of the given items and publishes every price that moved
+    /// beyond the threshold
+    ///
+    /// Nothing gets published while the circuit breaker is tripped, suggestions that don't
+    /// pass the guard are held for review or dropped. The deliveries run in the background
+    /// so this doesn't block the caller
+    pub async fn check_items(&self, items: HashSet<String>) {
+        match self.db.get_breaker().await {
+            Ok(Some(trip)) => {
+                debug!(
+                    "Circuit breaker is tripped ({}), not pricing {} items",
+                    trip.message,
+                    items.len()
+                );
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to get the circuit breaker: {:?}", e);
+                return;
+            }
+        }
+
+        for item in items {
+            let listings = match self.db.get_listings_for_item(&item).await {
+                Ok(listings) => listings,
+                Err(e) => {
+                    error!("Failed to get listings for item {}: {:?}", item, e);
+                    continue;
+                }
+            };
+
+            let mut new = PriceSuggestion::from_listings(&item, &listings);
+            self.config.margin.apply(&mut new);
+
+            let old = match self.db.get_price(&item).await {
+                Ok(old) => old,
+                Err(e) => {
+                    error!("Failed to get the last price of item {}: {:?}", item, e);
+                    continue;
+                }
+            };
+
+            if let Some(old) = &old {
+                let change = new.change_from(old);
+                if change < self.threshold {
+                    debug!("Price of item {} changed by {:.2}%", item, change);
+
+                    // the market came back, a held suggestion is outdated
+                    if let Err(e) = self.db.remove_held_price(&item).await {
+                        error!("Failed to update the review queue of {}: {:?}", item, e);
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(reason) = self.config.guard.check(old.as_ref(), &new) {
+                self.guard_suggestion(old, new, reason).await;
+                continue;
+            }
+
+            let change = match self.publish(old, new).await {
+                Ok(Some(change)) => change,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Failed to store the price of item {}: {:?}", item, e);
+                    continue;
+                }
+            };
+
+            if self.urls.is_empty() {
+                continue;
+            }
+
+            let notifier = self.clone();
+            tokio::spawn(async move {
+                notifier.notify(&change).await;
+            });
+        }
+    }
+
+    /// Holds the suggestion for review or drops it, depending on the guard mode
+    async fn guard_suggestion(
+        &self,
+        old: Option<PriceSuggestion>,
+        new: PriceSuggestion,
+        reason: GuardReason,
+    ) {
+        match self.config.guard.mode() {
+            GuardMode::Reject => {
+                warn!(
+                    "Rejected the price of item {} ({:?}), buy: {:?}, sell: {:?}",
+                    new.item, reason, new.buy, new.sell
+                );
+            }
+            GuardMode::Hold => {
+                warn!(
+                    "Holding the price of item {} for review ({:?}), buy: {:?}, sell: {:?}",
+                    new.item, reason, new.buy, new.sell
+                );
+
+                let held = HeldPrice {
+                    item: new.item.clone(),
+                    old,
+                    new,
+                    reason,
+                    time: Utc::now().timestamp(),
+                };
+
+                if let Err(e) = self.db.hold_price(&held).await {
+                    error!("Failed to hold the price of item {}: {:?}", held.item, e);
+                }
+            }
+        }
+    }
+
+    /// Stores the suggestion as the price of its item and publishes it on redis, returns
+    /// the change that should go to the webhooks
+    ///
+    /// Suggestions of overridden items are stored but not published
+    pub async fn publish(
+        &self,
+        old: Option<PriceSuggestion>,
+        new: PriceSuggestion,
+    ) -> Result<Option<PriceChange>, RedisError> {
+        self.db.set_price(&new).await?;
+        self.db.remove_held_price(&new.item).await?;
+
+        // the suggestion is kept up to date, but the override is what consumers see
+        if let Some(price) = self.db.get_override(&new.item).await? {
+            debug!(
+                "Not publishing the price of item {}, it is overridden by {}",
+                new.item, price.author
+            );
+            return Ok(None);
+        }
+
+        info!(
+            "Price of item {} changed from {:?} to buy: {:?}, sell: {:?}",
+            new.item,
+            old.as_ref().map(|old| (old.buy, old.sell)),
+            new.buy,
+            new.sell
+        );
+
+        let change = PriceChange {
+            event: PRICE_CHANGE_EVENT.to_owned(),
+            item: new.item.clone(),
+            old,
+            new,
+            overridden: false,
+            price: None,
+            time: Utc::now().timestamp(),
+        };
+
+        if let Err(e) = self.db.publish_price_change(&change).await {
+            error!(
+                "Failed to publish price change of item {}: {:?}",
+                change.item, e
+            );
+        }
+
+        Ok(Some(change))
+    }
+
+    /// Publishes the suggestion the guard held back for the item, returns `None` if
+    /// nothing waits for review
+    pub async fn approve(&self, item: &str) -> Result<Option<HeldPrice>, RedisError> {
+        let Some(held) = self.db.get_held_price(item).await? else {
+            return Ok(None);
+        };
+
+        let old = self.db.get_price(item).await?;
+        if let Some(change) = self.publish(old, held.new.clone()).await? {
+            self.notify(&change).await;
+        }
+
+        Ok(Some(held))
+    }
+
+    /// Stores the override and publishes it as the new price of its item
+    pub async fn set_override(&self, price: &PriceOverride) -> Result<(), RedisError> {
+        let old = self.current_price(&price.item).await?;
+        self.db.set_override(price).await?;
+
+        self.publish_override(OVERRIDE_SET_EVENT, &price.item, old)
+            .await
+    }
+
+    /// Removes the override of the item and publishes its suggestion again, returns
+    /// whether there was an override
+    pub async fn remove_override(&self, item: &str) -> Result<bool, RedisError> {
+        let old = self.current_price(item).await?;
+        if !self.db.remove_override(item).await? {
+            return Ok(false);
+        }
+
+        self.publish_override(OVERRIDE_REMOVE_EVENT, item, old)
+            .await?;
+        Ok(true)
+    }
+
+    /// Removes the overrides that expired and publishes the suggestions of their items
+    /// again, returns how many got removed
+    pub async fn remove_expired_overrides(&self) -> Result<usize, RedisError> {
+        let removed = self.db.remove_expired_overrides().await?;
+
+        for price in &removed {
+            let old = self.override_suggestion(price).await?;
+            self.publish_override(OVERRIDE_REMOVE_EVENT, &price.item, Some(old))
+                .await?;
+        }
+
+        Ok(removed.len())
+    }
+
+    /// The price consumers see, the override of the item or its last published suggestion
+    async fn current_price(&self, item: &str) -> Result<Option<PriceSuggestion>, RedisError> {
+        match self.db.get_override(item).await? {
+            Some(price) => Ok(Some(self.override_suggestion(&price).await?)),
+            None => self.db.get_price(item).await,
+        }
+    }
+
+    /// The override in refined, the listing counts are the ones of the last suggestion
+    async fn override_suggestion(
+        &self,
+        price: &PriceOverride,
+    ) -> Result<PriceSuggestion, RedisError> {
+        let key_price = get_key_price(&self.db, &self.config).await?;
+        let suggestion = self.db.get_price(&price.item).await?;
+
+        Ok(PriceSuggestion {
+            item: price.item.clone(),
+            buy: price.buy.to_refined(key_price),
+            sell: price.sell.to_refined(key_price),
+            buy_listings: suggestion.as_ref().map_or(0, |s| s.buy_listings),
+            sell_listings: suggestion.as_ref().map_or(0, |s| s.sell_listings),
+            time: price.time,
+            margin: None,
+        })
+    }
+
+    /// Publishes the price the item has after its override changed on redis and to the
+    /// webhooks, nothing gets published for items that have neither an override nor a
+    /// suggestion
+    async fn publish_override(
+        &self,
+        event: &str,
+        item: &str,
+        old: Option<PriceSuggestion>,
+    ) -> Result<(), RedisError> {
+        let (new, price) = match self.db.get_override(item).await? {
+            Some(price) => {
+                let tracked = match self.db.get_tracked_item(item).await? {
+                    Some(tracked) => tracked,
+                    None => TrackedItem {
+                        name: item.to_owned(),
+                        sku: None,
+                    },
+                };
+
+                (
+                    self.override_suggestion(&price).await?,
+                    Some(ItemPrice::from_override(&tracked, &price)),
+                )
+            }
+            None => match self.db.get_price(item).await? {
+                Some(suggestion) => (suggestion, None),
+                None => return Ok(()),
+            },
+        };
+
+        let change = PriceChange {
+            event: event.to_owned(),
+            item: item.to_owned(),
+            old,
+            new,
+            overridden: price.is_some(),
+            price,
+            time: Utc::now().timestamp(),
+        };
+
+        if let Err(e) = self.db.publish_price_change(&change).await {
+            error!(
+                "Failed to publish price change of item {}: {:?}",
+                change.item, e
+            );
+        }
+
+        self.notify(&change).await;
+        Ok(())
+    }
+
+    /// Posts the change to every webhook
+    pub async fn notify(&self, change: &PriceChange) {
+        let body = serde_json::to_string(change).unwrap();
+        let signature = self.sign(&body);
+
+        for url in &self.urls {
+            if let Err(e) = self.deliver(url, &body, signature.as_deref()).await {
+                error!(
+                    "Failed to deliver price change of item {} to {}: {}",
+                    change.item, url, e
+                );
+
+                let entry = json!({
+                    "url": url,
+                    "payload": change,
+                    "error": e,
+                    "failed_at": Utc::now().timestamp(),
+                });
+
+                if let Err(e) = self.db.push_webhook_dead_letter(&entry).await {
+                    error!("Failed to store webhook dead letter: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Posts the body to the url, retrying with an exponential backoff of at most 64 seconds
+    async fn deliver(&self, url: &str, body: &str, signature: Option<&str>) -> Result<(), String> {
+        let mut attempt = 0;
+
+        loop {
+            let mut req = self
+                .req_client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(body.to_owned());
+
+            if let Some(signature) = signature {
+                req = req.header(SIGNATURE_HEADER, format!("sha256={}", signature));
+            }
+
+            let error = match req.send().await {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => format!("webhook responded with status {}", res.status()),
+                Err(e) => format!("{:?}", e),
+            };
+
+            if attempt >= self.max_retries {
+                return Err(error);
+            }
+
+            attempt += 1;
+            warn!(
+                "Webhook delivery to {} failed ({}), retry {}/{}",
+                url, error, attempt, self.max_retries
+            );
+            tokio::time::sleep(Duration::from_secs(
+                1u64 << attempt.min(MAX_RETRY_DELAY_SHIFT),
+            ))
+            .await;
+        }
+    }
+
+    /// Signs the body with the webhook secret, if one is set
+    fn sign(&self, body: &str) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(body.as_bytes());
+
+        Some(
+            mac.finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use log::{debug, warn};
+use redis::RedisError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tf2_sku::SKU;
+
+use crate::{
+    db::Database,
+    items::TrackedItem,
+    notifier::Notifier,
+    pricing::{
+        get_key_price, get_suggestion, ItemCurrencies, ItemPrice, PriceConfig, PriceOverride,
+    },
+};
+
+/// Author of the overrides created by a pricelist import
+pub const PRICELIST_AUTHOR: &str = "pricelist import";
+
+/// One entry of a tf2autobot `pricelist.json`
+///
+/// Fields the pricer doesn't know about are kept in `extra` so they survive a round trip
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PricelistEntry {
+    pub sku: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Entries that aren't autopriced keep their own price, they are imported as overrides
+    #[serde(default = "default_true")]
+    pub autoprice: bool,
+    /// Minimum stock the bot keeps
+    #[serde(default)]
+    pub min: i64,
+    /// Maximum stock the bot keeps
+    #[serde(default = "default_max")]
+    pub max: i64,
+    /// 0 is buy only, 1 sell only and 2 bank
+    #[serde(default = "default_intent")]
+    pub intent: u8,
+    #[serde(default = "zero_currencies")]
+    pub buy: ItemCurrencies,
+    #[serde(default = "zero_currencies")]
+    pub sell: ItemCurrencies,
+    #[serde(default)]
+    pub time: Option<i64>,
+    #[serde(default)]
+    pub promoted: u8,
+    #[serde(default = "default_group")]
+    pub group: String,
+    #[serde(default = "default_note")]
+    pub note: Value,
+    #[serde(default)]
+    pub is_partial_priced: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max() -> i64 {
+    1
+}
+
+fn default_intent() -> u8 {
+    2
+}
+
+fn zero_currencies() -> ItemCurrencies {
+    ItemCurrencies {
+        keys: 0,
+        metal: 0.0,
+    }
+}
+
+fn default_group() -> String {
+    "all".to_owned()
+}
+
+fn default_note() -> Value {
+    serde_json::json!({ "buy": null, "sell": null })
+}
+
+impl PricelistEntry {
+    /// Creates an autopriced entry with the tf2autobot defaults
+    pub fn new(sku: &str) -> Self {
+        Self {
+            sku: sku.to_owned(),
+            name: None,
+            enabled: true,
+            autoprice: true,
+            min: 0,
+            max: default_max(),
+            intent: default_intent(),
+            buy: zero_currencies(),
+            sell: zero_currencies(),
+            time: None,
+            promoted: 0,
+            group: default_group(),
+            note: default_note(),
+            is_partial_priced: false,
+            extra: Map::new(),
+        }
+    }
+}
+
+/// Both layouts tf2autobot has used, a plain array and an object keyed by sku
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PricelistFile {
+    List(Vec<PricelistEntry>),
+    Keyed(HashMap<String, PricelistEntry>),
+}
+
+/// Parses the content of a `pricelist.json`
+pub fn parse_pricelist(content: &str) -> Result<Vec<PricelistEntry>, serde_json::Error> {
+    Ok(match serde_json::from_str(content)? {
+        PricelistFile::List(entries) => entries,
+        PricelistFile::Keyed(entries) => entries.into_values().collect(),
+    })
+}
+
+/// Returns the sku in the canonical form of `tf2-sku`, or `None` if it isn't a valid sku
+pub fn normalize_sku(sku: &str) -> Option<String> {
+    SKU::try_from(sku).ok().map(|sku| sku.to_string())
+}
+
+/// Rounds the metal to the nearest weapon the way tf2autobot stores it
+fn normalize_currencies(currencies: ItemCurrencies) -> ItemCurrencies {
+    ItemCurrencies::from(currencies.to_currencies())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct ImportStats {
+    pub entries: usize,
+    pub tracked: usize,
+    pub overrides: usize,
+    /// Entries whose price wasn't imported, the item is overridden by someone else
+    pub kept_overrides: usize,
+    pub skipped: usize,
+}
+
+/// Seeds the tracked items, overrides and stock bounds from the pricelist entries
+///
+/// Entries without a name are matched to an already tracked item by their sku, entries
+/// that match neither get skipped since backpack.tf listings are synced by name. The
+/// overrides are set through the notifier, so their prices get published
+///
+/// Overrides set by someone else than a pricelist import are kept unless `force` is set
+pub async fn import_pricelist(
+    db: &Database,
+    notifier: &Notifier,
+    entries: Vec<PricelistEntry>,
+    force: bool,
+) -> Result<ImportStats, RedisError> {
+    let mut stats = ImportStats {
+        entries: entries.len(),
+        ..Default::default()
+    };
+    let mut tracked_items = Vec::with_capacity(entries.len());
+
+    for mut entry in entries {
+        let Some(sku) = normalize_sku(&entry.sku) else {
+            warn!("Skipping pricelist entry with invalid sku {}", entry.sku);
+            stats.skipped += 1;
+            continue;
+        };
+
+        let name = match entry.name.clone() {
+            Some(name) => name,
+            None => match db.find_tracked_item(&sku).await? {
+                Some(item) => item.name,
+                None => {
+                    warn!("Skipping pricelist entry {} without a name", sku);
+                    stats.skipped += 1;
+                    continue;
+                }
+            },
+        };
+
+        entry.sku = sku.clone();
+        entry.name = Some(name.clone());
+        entry.buy = normalize_currencies(entry.buy);
+        entry.sell = normalize_currencies(entry.sell);
+
+        if !entry.autoprice {
+            match db.get_override(&name).await? {
+                // a price pinned by hand wins over the imported one
+                Some(price) if !force && price.author != PRICELIST_AUTHOR => {
+                    warn!(
+                        "Keeping the override of {} by {}, not importing its price",
+                        name, price.author
+                    );
+                    stats.kept_overrides += 1;
+                }
+                _ => {
+                    notifier
+                        .set_override(&PriceOverride {
+                            item: name.clone(),
+                            buy: entry.buy,
+                            sell: entry.sell,
+                            time: entry.time.unwrap_or_else(|| Utc::now().timestamp()),
+                            author: PRICELIST_AUTHOR.to_owned(),
+                            reason: "Not autopriced in the imported pricelist".to_owned(),
+                            expires_at: None,
+                        })
+                        .await?;
+                    stats.overrides += 1;
+                }
+            }
+        }
+
+        db.set_pricelist_entry(&name, &entry).await?;
+        tracked_items.push(TrackedItem {
+            name,
+            sku: Some(sku),
+        });
+    }
+
+    stats.tracked = tracked_items.len();
+    db.add_tracked_items(&tracked_items).await?;
+
+    Ok(stats)
+}
+
+/// Builds a complete pricelist of the tracked items
+///
+/// Overridden items get their override, the other autopriced ones the current suggestion.
+/// The stored settings of imported entries are kept, items without a valid sku or price
+/// are left out
+pub async fn build_pricelist(
+    db: &Database,
+    config: &PriceConfig,
+) -> Result<Vec<PricelistEntry>, RedisError> {
+    let items = db.get_tracked_items().await?;
+    let mut stored = db.get_pricelist_entries().await?;
+    let key_price = get_key_price(db, config).await?;
+
+    let mut pricelist = Vec::with_capacity(items.len());
+    for item in items {
+        let Some(sku) = item.sku.as_deref().and_then(normalize_sku) else {
+            debug!(
+                "Leaving {} out of the pricelist, it has no valid sku",
+                item.name
+            );
+            continue;
+        };
+
+        let mut entry = stored
+            .remove(&item.name)
+            .unwrap_or_else(|| PricelistEntry::new(&sku));
+        entry.sku = sku;
+        entry.name = Some(item.name.clone());
+
+        // overrides win over the suggestion, entries that aren't autopriced keep their
+        // imported price otherwise
+        if let Some(price) = db.get_override(&item.name).await? {
+            entry.buy = price.buy;
+            entry.sell = price.sell;
+            entry.time = Some(price.time);
+        } else if entry.autoprice {
+            let suggestion = get_suggestion(db, config, &item.name).await?;
+            let Some(price) = ItemPrice::new(&item, &suggestion, key_price) else {
+                warn!(
+                    "Leaving {} out of the pricelist, it has no price",
+                    item.name
+                );
+                continue;
+            };
+
+            entry.buy = price.buy;
+            entry.sell = price.sell;
+            entry.time = Some(price.time);
+        }
+
+        pricelist.push(entry);
+    }
+
+    Ok(pricelist)
+}
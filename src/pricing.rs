@@ -0,0 +1,423 @@
+use chrono::Utc;
+#[cfg(feature = "redis")]
+use redis::RedisError;
+use serde::{Deserialize, Serialize};
+use tf2_price::{get_metal_float_from_weapons, get_weapons_from_metal_float, Currencies, Rounding};
+
+#[cfg(feature = "redis")]
+use crate::db::Database;
+use crate::{event::UniversalListing, items::TrackedItem, margin::MarginStatus};
+#[cfg(feature = "redis")]
+use crate::{guard::PriceGuard, margin::MarginPolicy};
+
+/// Tolerance factor used to remove outliers around the median price
+const OUTLIER_TOLERANCE: f32 = 1.2;
+
+/// Name of the key item, its own suggestion is used as the key price
+pub const KEY_ITEM: &str = "Mann Co. Supply Crate Key";
+
+/// Value reported as the `source` of the tf2autobot compatible prices
+pub const PRICE_SOURCE: &str = "bp-pricer";
+
+/// A price suggestion for one item, all prices are in refined metal
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PriceSuggestion {
+    pub item: String,
+    pub buy: Option<f32>,
+    pub sell: Option<f32>,
+    pub buy_listings: usize,
+    pub sell_listings: usize,
+    pub time: i64,
+    /// Outcome of the minimum margin stage, `None` if it didn't apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margin: Option<MarginStatus>,
+}
+
+impl PriceSuggestion {
+    /// Creates a suggestion from the stored listings of the item
+    ///
+    /// Both sides are averaged independently after removing the outliers
+    pub fn from_listings(item: &str, listings: &[UniversalListing]) -> Self {
+        let buy_prices: Vec<f32> = listings
+            .iter()
+            .filter(|listing| listing.intent == "buy")
+            .map(|listing| listing.price)
+            .collect();
+        let sell_prices: Vec<f32> = listings
+            .iter()
+            .filter(|listing| listing.intent == "sell")
+            .map(|listing| listing.price)
+            .collect();
+
+        Self {
+            item: item.to_owned(),
+            buy_listings: buy_prices.len(),
+            sell_listings: sell_prices.len(),
+            buy: average_without_outliers(buy_prices),
+            sell: average_without_outliers(sell_prices),
+            time: Utc::now().timestamp(),
+            margin: None,
+        }
+    }
+
+    /// Returns the biggest relative change of the buy or sell price in percent
+    ///
+    /// A side that appeared or disappeared counts as an infinite change
+    pub fn change_from(&self, previous: &PriceSuggestion) -> f32 {
+        relative_change(previous.buy, self.buy).max(relative_change(previous.sell, self.sell))
+    }
+
+    /// Returns the biggest relative change in percent of the sides that are priced in both
+    pub fn priced_change_from(&self, previous: &PriceSuggestion) -> f32 {
+        let change = |old: Option<f32>, new: Option<f32>| match (old, new) {
+            (Some(_), Some(_)) => relative_change(old, new),
+            _ => 0.0,
+        };
+
+        change(previous.buy, self.buy).max(change(previous.sell, self.sell))
+    }
+
+    /// Whether the buy price is above the sell price
+    pub fn is_crossed(&self) -> bool {
+        matches!((self.buy, self.sell), (Some(buy), Some(sell)) if buy > sell)
+    }
+}
+
+fn relative_change(old: Option<f32>, new: Option<f32>) -> f32 {
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            if old == 0.0 {
+                return if new == 0.0 { 0.0 } else { f32::INFINITY };
+            }
+
+            ((new - old) / old).abs() * 100.0
+        }
+        (None, None) => 0.0,
+        _ => f32::INFINITY,
+    }
+}
+
+/// Lowest and highest price that aren't outliers, within `OUTLIER_TOLERANCE` of the median,
+/// `None` without prices
+pub(crate) fn outlier_bounds(prices: &[f32]) -> Option<(f32, f32)> {
+    let mut prices = prices.to_vec();
+    prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median_price = *prices.get(prices.len() / 2)?;
+
+    Some((
+        median_price / OUTLIER_TOLERANCE,
+        median_price * OUTLIER_TOLERANCE,
+    ))
+}
+
+/// Averages the prices after removing the ones too far away from the median
+fn average_without_outliers(prices: Vec<f32>) -> Option<f32> {
+    let (lower_bound, upper_bound) = outlier_bounds(&prices)?;
+
+    let prices: Vec<f32> = prices
+        .into_iter()
+        .filter(|price| *price >= lower_bound && *price <= upper_bound)
+        .collect();
+
+    if prices.is_empty() {
+        return None;
+    }
+
+    Some(prices.iter().sum::<f32>() / prices.len() as f32)
+}
+
+/// Settings of the price lookups, read from the env once at startup
+#[cfg(feature = "redis")]
+#[derive(Debug, Clone, Copy)]
+pub struct PriceConfig {
+    /// Fixed key price in refined, `KEY_PRICE`
+    pub key_price: Option<f32>,
+    /// Applied to the suggestions before they get published
+    pub margin: MarginPolicy,
+    /// Checks the suggestions before they get published
+    pub guard: PriceGuard,
+}
+
+#[cfg(feature = "redis")]
+impl PriceConfig {
+    pub fn new(key_price: Option<f32>, margin: MarginPolicy, guard: PriceGuard) -> Self {
+        Self {
+            key_price,
+            margin,
+            guard,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let key_price = std::env::var("KEY_PRICE")
+            .ok()
+            .map(|key_price| key_price.parse().expect("KEY_PRICE is not a number"));
+
+        Self::new(key_price, MarginPolicy::from_env(), PriceGuard::from_env())
+    }
+}
+
+/// Get the key price in refined
+///
+/// `KEY_PRICE` wins over an override of the key, which wins over the sell price
+/// suggested for the key itself
+#[cfg(feature = "redis")]
+pub async fn get_key_price(db: &Database, config: &PriceConfig) -> Result<Option<f32>, RedisError> {
+    if let Some(key_price) = config.key_price {
+        return Ok(Some(key_price));
+    }
+
+    if let Some(price) = db.get_override(KEY_ITEM).await? {
+        return Ok(Some(price.sell.metal));
+    }
+
+    Ok(db.get_price(KEY_ITEM).await?.and_then(|price| price.sell))
+}
+
+/// Get the current suggestion of the item
+///
+/// This is the last published price, items that were never published get computed
+/// from the stored listings with the minimum margin applied. Like a publish that
+/// suggestion has no price while the circuit breaker is tripped or if it doesn't pass
+/// the price guard
+#[cfg(feature = "redis")]
+pub async fn get_suggestion(
+    db: &Database,
+    config: &PriceConfig,
+    item: &str,
+) -> Result<PriceSuggestion, RedisError> {
+    if let Some(price) = db.get_price(item).await? {
+        return Ok(price);
+    }
+
+    let listings = db.get_listings_for_item(item).await?;
+    let mut suggestion = PriceSuggestion::from_listings(item, &listings);
+    config.margin.apply(&mut suggestion);
+
+    if db.get_breaker().await?.is_some() || config.guard.check(None, &suggestion).is_some() {
+        suggestion.buy = None;
+        suggestion.sell = None;
+        suggestion.margin = None;
+    }
+
+    Ok(suggestion)
+}
+
+/// Get the tf2autobot compatible price of the item
+///
+/// An active override takes precedence over the suggestion, `None` if there is neither an
+/// override nor a buy and sell price
+#[cfg(feature = "redis")]
+pub async fn get_item_price(
+    db: &Database,
+    config: &PriceConfig,
+    item: &TrackedItem,
+    key_price: Option<f32>,
+) -> Result<Option<ItemPrice>, RedisError> {
+    if let Some(price) = db.get_override(&item.name).await? {
+        return Ok(Some(ItemPrice::from_override(item, &price)));
+    }
+
+    let suggestion = get_suggestion(db, config, &item.name).await?;
+    Ok(ItemPrice::new(item, &suggestion, key_price))
+}
+
+/// Get the tf2autobot compatible prices of all the tracked items that have a price
+#[cfg(feature = "redis")]
+pub async fn get_item_prices(
+    db: &Database,
+    config: &PriceConfig,
+) -> Result<Vec<ItemPrice>, RedisError> {
+    let items = db.get_tracked_items().await?;
+    let key_price = get_key_price(db, config).await?;
+
+    let mut prices = Vec::with_capacity(items.len());
+    for item in items {
+        if let Some(price) = get_item_price(db, config, &item, key_price).await? {
+            prices.push(price);
+        }
+    }
+
+    Ok(prices)
+}
+
+/// Keys and metal the way tf2autobot expects them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ItemCurrencies {
+    pub keys: i64,
+    pub metal: f32,
+}
+
+impl ItemCurrencies {
+    /// Converts a price in refined into keys and metal, rounded to the nearest scrap
+    /// in the given direction
+    ///
+    /// Without a key price everything is expressed as metal
+    pub fn from_refined(refined: f32, key_price: Option<f32>, rounding: Rounding) -> Self {
+        let weapons = Currencies {
+            keys: 0,
+            weapons: get_weapons_from_metal_float(refined),
+        }
+        .round(&rounding)
+        .weapons;
+
+        let currencies = match key_price {
+            Some(key_price) if key_price > 0.0 => {
+                Currencies::from_weapons(weapons, get_weapons_from_metal_float(key_price))
+            }
+            _ => Currencies { keys: 0, weapons },
+        };
+
+        Self {
+            keys: currencies.keys,
+            metal: get_metal_float_from_weapons(currencies.weapons),
+        }
+    }
+
+    /// The price in refined, `None` if it has keys but there is no key price
+    pub fn to_refined(self, key_price: Option<f32>) -> Option<f32> {
+        match (self.keys, key_price) {
+            (0, _) => Some(self.metal),
+            (keys, Some(key_price)) => Some(keys as f32 * key_price + self.metal),
+            (_, None) => None,
+        }
+    }
+
+    pub fn to_currencies(self) -> Currencies {
+        Currencies {
+            keys: self.keys,
+            weapons: get_weapons_from_metal_float(self.metal),
+        }
+    }
+}
+
+impl From<Currencies> for ItemCurrencies {
+    fn from(currencies: Currencies) -> Self {
+        Self {
+            keys: currencies.keys,
+            metal: get_metal_float_from_weapons(currencies.weapons),
+        }
+    }
+}
+
+/// A manually set price of an item, used instead of its suggestion until it expires
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PriceOverride {
+    pub item: String,
+    pub buy: ItemCurrencies,
+    pub sell: ItemCurrencies,
+    /// When the override was set
+    pub time: i64,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub reason: String,
+    /// Unix timestamp after which the suggestion is used again, `None` pins the price
+    /// until the override gets removed
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+impl PriceOverride {
+    /// Creates an override set now, the metal gets rounded to the nearest weapon
+    ///
+    /// Returns why the override is invalid: a missing author or reason, negative prices,
+    /// a buy price above the sell price or an expiry in the past
+    pub fn new(
+        item: &str,
+        buy: ItemCurrencies,
+        sell: ItemCurrencies,
+        author: &str,
+        reason: &str,
+        expires_at: Option<i64>,
+    ) -> Result<Self, &'static str> {
+        let now = Utc::now().timestamp();
+        let buy = buy.to_currencies();
+        let sell = sell.to_currencies();
+
+        if author.trim().is_empty() {
+            return Err("The override needs an author");
+        }
+        if reason.trim().is_empty() {
+            return Err("The override needs a reason");
+        }
+        if buy.keys < 0 || buy.weapons < 0 || sell.keys < 0 || sell.weapons < 0 {
+            return Err("Prices can't be negative");
+        }
+        if buy > sell {
+            return Err("The buy price is above the sell price");
+        }
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err("The override would already be expired");
+        }
+
+        Ok(Self {
+            item: item.to_owned(),
+            buy: buy.into(),
+            sell: sell.into(),
+            time: now,
+            author: author.trim().to_owned(),
+            reason: reason.trim().to_owned(),
+            expires_at,
+        })
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A price in the shape of the tf2autobot price source
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ItemPrice {
+    pub sku: String,
+    pub name: String,
+    pub buy: ItemCurrencies,
+    pub sell: ItemCurrencies,
+    pub time: i64,
+    pub source: String,
+    /// Whether the price comes from a manual override instead of the suggestion
+    #[serde(default)]
+    pub overridden: bool,
+}
+
+impl ItemPrice {
+    /// Creates the price from a suggestion, returns `None` if either side has no price
+    ///
+    /// The buy price gets rounded down and the sell price up to the next scrap
+    pub fn new(
+        item: &TrackedItem,
+        suggestion: &PriceSuggestion,
+        key_price: Option<f32>,
+    ) -> Option<Self> {
+        // the key itself is always priced in metal
+        let key_price = if item.name == KEY_ITEM {
+            None
+        } else {
+            key_price
+        };
+
+        Some(Self {
+            sku: item.sku_or_name().to_owned(),
+            name: item.name.clone(),
+            buy: ItemCurrencies::from_refined(suggestion.buy?, key_price, Rounding::DownScrap),
+            sell: ItemCurrencies::from_refined(suggestion.sell?, key_price, Rounding::UpScrap),
+            time: suggestion.time,
+            source: PRICE_SOURCE.to_owned(),
+            overridden: false,
+        })
+    }
+
+    /// Creates the price from an override, its keys and metal are used as they are
+    pub fn from_override(item: &TrackedItem, price: &PriceOverride) -> Self {
+        Self {
+            sku: item.sku_or_name().to_owned(),
+            name: item.name.clone(),
+            buy: price.buy,
+            sell: price.sell,
+            time: price.time,
+            source: PRICE_SOURCE.to_owned(),
+            overridden: true,
+        }
+    }
+}
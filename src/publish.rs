@@ -0,0 +1,76 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{event::UniversalListing, pricing::PriceOverride};
+
+/// Pub/sub channel that receives every listing change
+pub const LISTINGS_CHANNEL: &str = "pricer:listings";
+/// Pub/sub channel that receives every published price change
+pub const PRICES_CHANNEL: &str = "pricer:prices";
+/// Pub/sub channel that receives every override that got set or removed
+pub const OVERRIDES_CHANNEL: &str = "pricer:overrides";
+/// Stream that keeps the listing changes around so consumers can replay missed ones
+pub const LISTINGS_STREAM: &str = "pricer:stream:listings";
+/// Stream that keeps the price changes around so consumers can replay missed ones
+pub const PRICES_STREAM: &str = "pricer:stream:prices";
+
+/// A change of a stored listing, published on the `LISTINGS_CHANNEL` and appended
+/// to the `LISTINGS_STREAM`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ListingChange {
+    ListingUpdate {
+        key: String,
+        item: Option<String>,
+        listing: UniversalListing,
+        time: i64,
+    },
+    ListingDelete {
+        key: String,
+        item: Option<String>,
+        time: i64,
+    },
+}
+
+impl ListingChange {
+    pub fn update(key: &str, item: Option<&str>, listing: UniversalListing) -> Self {
+        ListingChange::ListingUpdate {
+            key: key.to_owned(),
+            item: item.map(|item| item.to_owned()),
+            listing,
+            time: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn delete(key: &str, item: Option<&str>) -> Self {
+        ListingChange::ListingDelete {
+            key: key.to_owned(),
+            item: item.map(|item| item.to_owned()),
+            time: Utc::now().timestamp(),
+        }
+    }
+}
+
+/// An override that got set or removed, published on the `OVERRIDES_CHANNEL`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum OverrideChange {
+    OverrideSet {
+        item: String,
+        price: PriceOverride,
+        time: i64,
+    },
+    OverrideRemove {
+        item: String,
+        time: i64,
+    },
+}
+
+impl OverrideChange {
+    pub fn item(&self) -> &str {
+        match self {
+            OverrideChange::OverrideSet { item, .. }
+            | OverrideChange::OverrideRemove { item, .. } => item,
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// Spaces out requests that are shared between concurrent tasks
+///
+/// Every request reserves the next free slot, so concurrent callers queue up instead of
+/// all firing at once. A rate limited response pauses everyone
+#[derive(Clone)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_slot: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Waits until the caller is allowed to send its request
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Holds back all requests for the given duration
+    pub async fn pause(&self, duration: Duration) {
+        let mut next_slot = self.next_slot.lock().await;
+        *next_slot = (*next_slot).max(Instant::now() + duration);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{path::PathBuf, time::Duration};
+
+use log::{error, info, warn};
+
+use crate::{
+    archive::{expand_archive_paths, ArchiveReader, ArchiveRecord},
+    book::LISTING_MAX_AGE,
+    bptf::ingest_frame,
+    db::Database,
+    event::DecodedFrame,
+    types::ListingResponse,
+};
+
+/// Record time between two removals of old listings in seconds, the live sync removes them
+/// after every round of snapshots
+const PRUNE_INTERVAL: i64 = 3600;
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// Archive files or directories holding archive files
+    pub paths: Vec<PathBuf>,
+    /// How much faster than real time the records are replayed, `None` replays without pauses
+    pub speed: Option<f64>,
+    /// Names of the items whose websocket listings are kept, like `ITEMS` for the live sync
+    pub items: Vec<String>,
+}
+
+/// What a replay went through
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplayStats {
+    pub frames: usize,
+    pub snapshots: usize,
+    /// Snapshot bodies that couldn't be parsed
+    pub invalid_snapshots: usize,
+    /// Listings removed for not being bumped within a day of the record time
+    pub pruned: usize,
+}
+
+/// Feeds archived websocket frames and snapshot responses through the same ingest code as
+/// the live sync, in the order they were received
+///
+/// The time of the records is used as the clock, for the tombstones of deleted listings and
+/// for removing the listings that weren't bumped for a day
+pub async fn replay(db: &Database, options: &ReplayOptions) -> ReplayStats {
+    let mut stats = ReplayStats::default();
+    let mut last_received_at: Option<i64> = None;
+    let mut last_pruned_at: Option<i64> = None;
+
+    for path in expand_archive_paths(&options.paths) {
+        let reader = match ArchiveReader::open(&path) {
+            Ok(reader) => reader,
+            Err(e) => {
+                error!("Failed to open archive file {:?}: {:?}", path, e);
+                continue;
+            }
+        };
+
+        info!("Replaying {:?}", path);
+
+        for record in reader {
+            if let (Some(speed), Some(last)) = (options.speed, last_received_at) {
+                let gap = (record.received_at() - last).max(0) as f64 / speed;
+                if gap >= 1.0 {
+                    tokio::time::sleep(Duration::from_millis(gap as u64)).await;
+                }
+            }
+            last_received_at = Some(record.received_at());
+
+            // the records are timestamped in milliseconds
+            let now = record.received_at() / 1000;
+            if now - *last_pruned_at.get_or_insert(now) >= PRUNE_INTERVAL {
+                stats.pruned += prune(db, now).await;
+                last_pruned_at = Some(now);
+            }
+
+            match record {
+                ArchiveRecord::Websocket { frame, .. } => {
+                    ingest_frame(db, DecodedFrame::decode(&frame), &options.items, now).await;
+                    stats.frames += 1;
+                }
+                ArchiveRecord::Snapshot { item, body, .. } => {
+                    let snapshot: ListingResponse = match serde_json::from_str(&body) {
+                        Ok(snapshot) => snapshot,
+                        Err(e) => {
+                            warn!("Skipping invalid snapshot of item {}: {:?}", item, e);
+                            stats.invalid_snapshots += 1;
+                            continue;
+                        }
+                    };
+
+                    if let Err(e) = db.set_snapshot_created_at(&item, snapshot.created_at).await {
+                        error!(
+                            "Failed to store snapshot creation time of item {}: {:?}",
+                            item, e
+                        );
+                    }
+
+                    if let Err(e) = db
+                        .update_listings_from_snapshot(
+                            snapshot.listings,
+                            &item,
+                            snapshot.created_at,
+                        )
+                        .await
+                    {
+                        error!("Failed to replay snapshot of item {}: {:?}", item, e);
+                    }
+                    stats.snapshots += 1;
+                }
+            }
+        }
+    }
+
+    if let Some(last_received_at) = last_received_at {
+        stats.pruned += prune(db, last_received_at / 1000).await;
+    }
+
+    stats
+}
+
+/// Removes the listings that were too old at the record time `now`
+async fn prune(db: &Database, now: i64) -> usize {
+    match db.remove_old_listings(LISTING_MAX_AGE, now).await {
+        Ok(removed) => removed,
+        Err(e) => {
+            error!("Failed to remove old listings: {:?}", e);
+            0
+        }
+    }
+}
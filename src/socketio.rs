@@ -0,0 +1,212 @@
+//! A minimal socket.io server for the tf2autobot custom pricer
+//!
+//! Only the websocket transport of Engine.IO v4 and the default namespace are
+//! supported, which is what tf2autobot uses. Every price is sent as a `price` event
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use log::{debug, warn};
+use serde_json::json;
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+use crate::api::AppState;
+
+/// How often the server pings the clients
+const PING_INTERVAL: Duration = Duration::from_secs(25);
+/// How long a client has to answer a ping
+const PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Used to keep the session ids unique within the process
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A decoded Engine.IO packet, socket.io packets are carried in `Message`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet<'a> {
+    Close,
+    Ping,
+    Pong,
+    /// Socket.io `CONNECT` to a namespace, `/` for the default one
+    Connect {
+        namespace: &'a str,
+    },
+    /// Socket.io `DISCONNECT`
+    Disconnect,
+    /// Any other socket.io packet, events from the client are ignored
+    Other,
+}
+
+impl<'a> Packet<'a> {
+    /// Parses a text frame, returns `None` for frames that aren't Engine.IO packets
+    pub fn parse(frame: &'a str) -> Option<Self> {
+        let mut chars = frame.chars();
+        let packet = match chars.next()? {
+            '1' => Packet::Close,
+            '2' => Packet::Ping,
+            '3' => Packet::Pong,
+            '4' => match chars.next()? {
+                '0' => {
+                    let rest = chars.as_str();
+                    // the namespace is followed by a comma, the auth payload comes after it
+                    let namespace = if rest.starts_with('/') {
+                        rest.split(',').next().unwrap_or(rest)
+                    } else {
+                        "/"
+                    };
+                    Packet::Connect { namespace }
+                }
+                '1' => Packet::Disconnect,
+                _ => Packet::Other,
+            },
+            _ => return None,
+        };
+
+        Some(packet)
+    }
+}
+
+/// Encodes a socket.io event packet for the default namespace, `data` is already serialized
+pub fn event_packet(event: &str, data: &str) -> String {
+    format!("42[{},{}]", json!(event), data)
+}
+
+fn session_id() -> String {
+    let counter = SESSION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}{:x}", Utc::now().timestamp_micros(), counter)
+}
+
+/// `GET /socket.io/`, only websocket upgrades are accepted, tf2autobot doesn't use polling
+pub async fn handler(
+    ws: Option<WebSocketUpgrade>,
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Response {
+    let (Some(ws), Some("websocket"), Some("4")) = (
+        ws,
+        query.get("transport").map(String::as_str),
+        query.get("EIO").map(String::as_str),
+    ) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "code": 0, "message": "Transport unknown" })),
+        )
+            .into_response();
+    };
+
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+/// Runs the Engine.IO session and sends every price once the client joined the default
+/// namespace
+async fn handle_socket(mut socket: WebSocket, state: AppState) {
+    let sid = session_id();
+    let open = json!({
+        "sid": sid,
+        "upgrades": [],
+        "pingInterval": PING_INTERVAL.as_millis() as u64,
+        "pingTimeout": PING_TIMEOUT.as_millis() as u64,
+        "maxPayload": 1_000_000,
+    });
+    if socket
+        .send(Message::Text(format!("0{}", open)))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let mut events = state.feed.subscribe();
+    let mut connected = false;
+    let mut ping = tokio::time::interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut pong_deadline: Option<Instant> = None;
+
+    loop {
+        let deadline = pong_deadline.unwrap_or_else(|| Instant::now() + PING_INTERVAL * 2);
+
+        tokio::select! {
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("Failed to read message from socket.io client: {:?}", e);
+                        return;
+                    }
+                };
+
+                let reply = match Packet::parse(&text) {
+                    Some(Packet::Pong) => {
+                        pong_deadline = None;
+                        continue;
+                    }
+                    Some(Packet::Ping) => "3".to_owned(),
+                    Some(Packet::Connect { namespace: "/" }) => {
+                        connected = true;
+                        format!("40{}", json!({ "sid": sid }))
+                    }
+                    Some(Packet::Connect { namespace }) => format!(
+                        "44{},{}",
+                        namespace,
+                        json!({ "message": "Invalid namespace" })
+                    ),
+                    Some(Packet::Close) | Some(Packet::Disconnect) => return,
+                    Some(Packet::Other) => continue,
+                    None => {
+                        debug!("Ignoring invalid socket.io frame {}", text);
+                        continue;
+                    }
+                };
+
+                if socket.send(Message::Text(reply)).await.is_err() {
+                    return;
+                }
+            }
+            _ = ping.tick() => {
+                if socket.send(Message::Text("2".to_owned())).await.is_err() {
+                    return;
+                }
+                pong_deadline.get_or_insert(Instant::now() + PING_TIMEOUT);
+            }
+            _ = tokio::time::sleep_until(deadline), if pong_deadline.is_some() => {
+                debug!("Socket.io client {} didn't answer the ping", sid);
+                return;
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Socket.io client fell behind, skipped {} messages", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let (true, Some(price)) = (connected, event.price) else {
+                    continue;
+                };
+
+                if socket
+                    .send(Message::Text(event_packet("price", &price)))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
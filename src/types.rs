@@ -1,4 +1,4 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use log::error;
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,8 @@ use serde_aux::prelude::deserialize_number_from_string;
 use tf_item_attributes::TFItemAttribute;
 use tokio::sync::oneshot::error;
 
+use crate::pricing::outlier_bounds;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PriceHistory {
     success: i8,
@@ -17,6 +19,7 @@ pub enum PricingError {
     InternalError,
     ServerError,
     IsAlreadyCached,
+    RateLimited,
 }
 
 impl PriceHistory {
@@ -85,6 +88,9 @@ pub struct UserAgent {
 pub struct Item {
     pub id: Option<u64>,
     original_id: Option<u64>,
+    /// Not part of the snapshot items, set to the name of the snapshot item before storing them
+    #[serde(default)]
+    pub name: Option<String>,
     pub defindex: u64,
     level: Option<u8>,
     quality: u32,
@@ -147,7 +153,7 @@ pub struct ItemAttribute {
 pub struct ListingResponse {
     pub listings: Vec<Listing>,
     #[serde(rename = "createdAt")]
-    created_at: u32,
+    pub created_at: u32,
 }
 
 impl ListingResponse {
@@ -196,24 +202,16 @@ impl ListingResponse {
         self
     }
 
-    /// Fitlters outliers from the listings
+    /// Fitlters outliers from the listings, the same way the price suggestions do
     pub fn filter_outliers(&mut self) -> &mut Self {
-        let mut prices: Vec<f32> = self.listings.iter().map(|l| l.price).collect();
-        prices.sort_by(|a, b| a.partial_cmp(b).unwrap()); // Sort prices for efficient median calculation
-        let median_price = prices[prices.len() / 2]; // Median is the middle element
-
-        // 20% tolerance factor
-        let tolerance_factor = 1.2;
-        let lower_bound = median_price / tolerance_factor;
-        let upper_bound = median_price * tolerance_factor;
+        let prices: Vec<f32> = self.listings.iter().map(|l| l.price).collect();
+        let Some((lower_bound, upper_bound)) = outlier_bounds(&prices) else {
+            return self;
+        };
 
         // Filter listings that are outside the tolerance bounds
-        self.listings = self
-            .listings
-            .iter()
-            .filter(|l| l.price >= lower_bound && l.price <= upper_bound)
-            .cloned()
-            .collect();
+        self.listings
+            .retain(|l| l.price >= lower_bound && l.price <= upper_bound);
 
         self
     }
@@ -265,3 +263,16 @@ impl ListingResponse {
         self.listings = listings;
     }
 }
+
+/// Parses a unix timestamp or an RFC 3339 date into a unix timestamp
+///
+/// Timestamps that don't fit in milliseconds are rejected, the price history is keyed by them
+pub fn parse_time(value: &str) -> Option<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return timestamp.checked_mul(1000).map(|_| timestamp);
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.timestamp())
+}
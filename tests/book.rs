@@ -0,0 +1,116 @@
+use pricer::{
+    book::{ListingBook, UpsertResult, LISTING_MAX_AGE, TOMBSTONE_TTL},
+    event::{listing_id, UniversalItem, UniversalListing},
+    types::StrIntValue,
+};
+
+const ITEM: &str = "Mann Co. Supply Crate Key";
+
+fn listing(asset_id: u64, price: f32, bumped_at: u32) -> UniversalListing {
+    UniversalListing {
+        id: Some(listing_id(
+            "sell",
+            "76561198000000000",
+            Some(asset_id),
+            ITEM,
+        )),
+        steamid: "76561198000000000".to_owned(),
+        details: None,
+        intent: "sell".to_owned(),
+        price,
+        bumped_at,
+        item: UniversalItem {
+            id: Some(StrIntValue::Int(asset_id)),
+            name: Some(ITEM.to_owned()),
+            defindex: 5021,
+        },
+    }
+}
+
+#[test]
+fn last_writer_wins() {
+    let mut book = ListingBook::new();
+
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 60.0, 100), 100),
+        UpsertResult::Created
+    );
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 60.0, 100), 110),
+        UpsertResult::Unchanged
+    );
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 61.0, 200), 200),
+        UpsertResult::Updated
+    );
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 59.0, 150), 210),
+        UpsertResult::Outdated
+    );
+
+    assert_eq!(book.lowest_sell(ITEM), Some(61.0));
+}
+
+#[test]
+fn tombstones_block_older_updates_until_they_expire() {
+    let mut book = ListingBook::new();
+    let key = listing(1, 60.0, 100).key();
+
+    book.upsert(ITEM, listing(1, 60.0, 100), 100);
+    assert!(book.delete(ITEM, &key, 150));
+    assert!(!book.delete(ITEM, &key, 150));
+
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 60.0, 120), 160),
+        UpsertResult::Deleted
+    );
+    assert!(book.listings(ITEM).is_empty());
+
+    // a relist is bumped after the deletion
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 62.0, 170), 170),
+        UpsertResult::Created
+    );
+    assert!(book.delete(ITEM, &key, 180));
+
+    let expired = 180 + TOMBSTONE_TTL;
+    assert_eq!(
+        book.upsert(ITEM, listing(1, 60.0, 120), expired),
+        UpsertResult::Created
+    );
+}
+
+#[test]
+fn snapshots_remove_vanished_listings() {
+    let mut book = ListingBook::new();
+
+    book.upsert(ITEM, listing(1, 60.0, 100), 100);
+    // bumped after the snapshot was created
+    book.upsert(ITEM, listing(2, 61.0, 300), 300);
+    // newer than its version in the snapshot
+    book.upsert(ITEM, listing(3, 62.0, 300), 300);
+
+    let removed = book.apply_snapshot(
+        ITEM,
+        vec![listing(3, 70.0, 150), listing(4, 63.0, 150)],
+        200,
+        310,
+    );
+    assert_eq!(removed, 1);
+
+    let mut prices: Vec<f32> = book.listings(ITEM).iter().map(|l| l.price).collect();
+    prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(prices, vec![61.0, 62.0, 63.0]);
+}
+
+#[test]
+fn listings_expire_a_day_after_their_last_bump() {
+    let mut book = ListingBook::new();
+
+    book.upsert(ITEM, listing(1, 60.0, 1000), 1000);
+    book.upsert(ITEM, listing(2, 61.0, 5000), 5000);
+
+    assert_eq!(book.remove_expired(1000 + LISTING_MAX_AGE), 0);
+    assert_eq!(book.remove_expired(1001 + LISTING_MAX_AGE), 1);
+    assert_eq!(book.lowest_sell(ITEM), Some(61.0));
+}
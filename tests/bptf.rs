@@ -0,0 +1,259 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{
+    clear_item, listing_delete, listing_update, now, snapshot_body, snapshot_listing, test_client,
+    test_db, MockBackpackTF, MockResponse,
+};
+use pricer::{bptf::SnapshotClient, types::PricingError};
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn snapshot_sync_creates_and_removes_listings() {
+    let db = test_db().await;
+    let item = "Integration Test Snapshot Item";
+    clear_item(item).await;
+
+    let mock = MockBackpackTF::start().await;
+    let bptf = test_client(&db, &mock);
+    let created_at = now();
+
+    mock.push_snapshot(
+        item,
+        MockResponse::ok(snapshot_body(
+            created_at,
+            vec![
+                snapshot_listing(
+                    "76561198000000001",
+                    "sell",
+                    10.11,
+                    Some(91001),
+                    9001,
+                    created_at,
+                ),
+                snapshot_listing(
+                    "76561198000000002",
+                    "sell",
+                    10.22,
+                    Some(91002),
+                    9001,
+                    created_at,
+                ),
+                snapshot_listing("76561198000000003", "buy", 9.88, None, 9001, created_at),
+            ],
+        )),
+    );
+    mock.push_snapshot(
+        item,
+        MockResponse::ok(snapshot_body(
+            created_at + 1,
+            vec![snapshot_listing(
+                "76561198000000001",
+                "sell",
+                10.11,
+                Some(91001),
+                9001,
+                created_at,
+            )],
+        )),
+    );
+
+    let snapshot = bptf.fetch_snapshot(item).await.unwrap();
+    let sync = bptf.store_snapshot(item, snapshot).await.unwrap();
+    assert_eq!(sync.created, 3);
+    assert_eq!(db.get_listings_for_item(item).await.unwrap().len(), 3);
+
+    let snapshot = bptf.fetch_snapshot(item).await.unwrap();
+    let sync = bptf.store_snapshot(item, snapshot).await.unwrap();
+    assert_eq!(sync.unchanged, 1);
+    assert_eq!(sync.removed, 2);
+
+    let listings = db.get_listings_for_item(item).await.unwrap();
+    assert_eq!(listings.len(), 1);
+    assert_eq!(listings[0].steamid, "76561198000000001");
+
+    let freshness = db.get_snapshot_freshness(item).await.unwrap();
+    assert_eq!(freshness.created_at, Some(created_at + 1));
+    assert!(freshness.fetched_at.is_some());
+}
+
+#[tokio::test]
+async fn snapshot_error_codes() {
+    let item = "Integration Test Error Item";
+
+    let mock = MockBackpackTF::start().await;
+    let client = SnapshotClient::new(
+        "token".to_owned(),
+        mock.base_url.clone(),
+        Duration::from_millis(0),
+    )
+    .unwrap();
+
+    mock.push_snapshot(item, MockResponse::status(500, "internal error"));
+    mock.push_snapshot(item, MockResponse::status(403, "forbidden"));
+    mock.push_snapshot(item, MockResponse::status(200, "not json"));
+    mock.push_snapshot(
+        item,
+        MockResponse::status(429, "slow down").header("Retry-After", "1"),
+    );
+    mock.push_snapshot(item, MockResponse::ok(snapshot_body(now(), vec![])));
+
+    assert_eq!(
+        client.fetch(item).await.unwrap_err(),
+        PricingError::ServerError
+    );
+    assert_eq!(
+        client.fetch(item).await.unwrap_err(),
+        PricingError::InternalError
+    );
+    assert_eq!(
+        client.fetch(item).await.unwrap_err(),
+        PricingError::InternalError
+    );
+    assert_eq!(
+        client.fetch(item).await.unwrap_err(),
+        PricingError::RateLimited
+    );
+
+    // the rate limited response holds back the next request for its Retry-After
+    let started = Instant::now();
+    assert!(client.fetch(item).await.is_ok());
+    assert!(started.elapsed() >= Duration::from_millis(900));
+
+    // nothing scripted anymore
+    assert_eq!(
+        client.fetch(item).await.unwrap_err(),
+        PricingError::InternalError
+    );
+    assert_eq!(mock.snapshot_requests(), 6);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn websocket_updates_and_deletes_listings() {
+    let db = test_db().await;
+    let item = "Integration Test Websocket Item";
+    clear_item(item).await;
+
+    let mock = MockBackpackTF::start().await;
+    let bptf = test_client(&db, &mock);
+    let bumped_at = now();
+
+    mock.push_session(vec![
+        json!([
+            listing_update("76561198000000011", "sell", 5.11, 92001, item, 9002, bumped_at),
+            listing_update("76561198000000012", "sell", 5.22, 92002, item, 9002, bumped_at),
+            listing_update("76561198000000013", "sell", 5.33, 92003, "Untracked Item", 9002, bumped_at),
+            { "event": "some-new-event", "payload": {} },
+        ]),
+        json!([listing_delete("76561198000000012", 92002, item, 9002)]),
+    ]);
+
+    bptf.watch_websocket(vec![item.to_owned()]).await;
+
+    let listings = db.get_listings_for_item(item).await.unwrap();
+    assert_eq!(listings.len(), 1);
+    assert_eq!(listings[0].steamid, "76561198000000011");
+    assert_eq!(listings[0].price, 5.11);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn websocket_reconnects_to_the_next_session() {
+    let db = test_db().await;
+    let item = "Integration Test Reconnect Item";
+    clear_item(item).await;
+
+    let mock = MockBackpackTF::start().await;
+    let bptf = test_client(&db, &mock);
+    let bumped_at = now();
+
+    mock.push_session(vec![json!([listing_update(
+        "76561198000000021",
+        "sell",
+        7.11,
+        93001,
+        item,
+        9003,
+        bumped_at
+    )])]);
+    mock.push_session(vec![json!([listing_update(
+        "76561198000000021",
+        "sell",
+        7.22,
+        93001,
+        item,
+        9003,
+        bumped_at + 1
+    )])]);
+
+    let runner = bptf.clone();
+    let items = vec![item.to_owned()];
+    let watcher = tokio::spawn(async move {
+        runner
+            .keep_websocket(items, Duration::from_millis(50))
+            .await;
+    });
+
+    // the loop reconnects after the first session closed and reads the second one
+    let started = Instant::now();
+    let price = loop {
+        let listings = db.get_listings_for_item(item).await.unwrap();
+        let price = listings.first().map(|listing| listing.price);
+        if price == Some(7.22) || started.elapsed() > Duration::from_secs(5) {
+            break price;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    watcher.abort();
+
+    assert_eq!(price, Some(7.22));
+    assert!(mock.connections() >= 2);
+    assert_eq!(db.get_listings_for_item(item).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn snapshot_and_websocket_share_listings() {
+    let db = test_db().await;
+    let item = "Integration Test Identity Item";
+    clear_item(item).await;
+
+    let mock = MockBackpackTF::start().await;
+    let bptf = test_client(&db, &mock);
+    let created_at = now();
+
+    mock.push_snapshot(
+        item,
+        MockResponse::ok(snapshot_body(
+            created_at,
+            vec![snapshot_listing(
+                "76561198000000031",
+                "sell",
+                3.11,
+                Some(94001),
+                9004,
+                created_at,
+            )],
+        )),
+    );
+    mock.push_session(vec![json!([listing_update(
+        "76561198000000031",
+        "sell",
+        3.22,
+        94001,
+        item,
+        9004,
+        created_at + 1
+    )])]);
+
+    let snapshot = bptf.fetch_snapshot(item).await.unwrap();
+    bptf.store_snapshot(item, snapshot).await.unwrap();
+    bptf.watch_websocket(vec![item.to_owned()]).await;
+
+    let listings = db.get_listings_for_item(item).await.unwrap();
+    assert_eq!(listings.len(), 1);
+    assert_eq!(listings[0].price, 3.22);
+}
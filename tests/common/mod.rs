@@ -0,0 +1,338 @@
+//! In-process mock of the backpack.tf snapshot endpoint and websocket
+
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use pricer::{
+    bptf::BackpackTF,
+    db::Database,
+    guard::{GuardMode, PriceGuard},
+    margin::{MarginPolicy, WidenDirection},
+    notifier::Notifier,
+    pricing::{PriceConfig, PriceSuggestion},
+};
+use redis::AsyncCommands;
+use serde_json::{json, Value};
+
+/// A canned response of the snapshot endpoint
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl MockResponse {
+    pub fn ok(body: Value) -> Self {
+        Self::status(200, &body.to_string())
+    }
+
+    pub fn status(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            body: body.to_owned(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+#[derive(Clone, Default)]
+struct MockState {
+    /// Responses of the snapshot endpoint per sku, served in order
+    snapshots: Arc<Mutex<HashMap<String, VecDeque<MockResponse>>>>,
+    /// Frames sent on each websocket connection, one entry per connection
+    sessions: Arc<Mutex<VecDeque<Vec<String>>>>,
+    snapshot_requests: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>,
+}
+
+pub struct MockBackpackTF {
+    pub base_url: String,
+    pub ws_url: String,
+    state: MockState,
+}
+
+impl MockBackpackTF {
+    /// Serves the mock on a random local port until the test ends
+    pub async fn start() -> Self {
+        let state = MockState::default();
+        let router = Router::new()
+            .route("/api/classifieds/listings/snapshot", get(snapshot))
+            .route("/events", get(events))
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        Self {
+            base_url: format!("http://{}/api", address),
+            ws_url: format!("ws://{}/events", address),
+            state,
+        }
+    }
+
+    pub fn push_snapshot(&self, sku: &str, response: MockResponse) {
+        self.state
+            .snapshots
+            .lock()
+            .unwrap()
+            .entry(sku.to_owned())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Queues the frames of the next websocket connection, the connection is closed after the
+    /// last frame
+    pub fn push_session(&self, frames: Vec<Value>) {
+        self.state
+            .sessions
+            .lock()
+            .unwrap()
+            .push_back(frames.iter().map(|frame| frame.to_string()).collect());
+    }
+
+    pub fn snapshot_requests(&self) -> usize {
+        self.state.snapshot_requests.load(Ordering::SeqCst)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn snapshot(
+    State(state): State<MockState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    state.snapshot_requests.fetch_add(1, Ordering::SeqCst);
+
+    let sku = query.get("sku").cloned().unwrap_or_default();
+    let response = state
+        .snapshots
+        .lock()
+        .unwrap()
+        .get_mut(&sku)
+        .and_then(|responses| responses.pop_front());
+
+    let Some(response) = response else {
+        return (StatusCode::NOT_FOUND, "no snapshot scripted").into_response();
+    };
+
+    let mut http_response = (
+        StatusCode::from_u16(response.status).unwrap(),
+        response.body,
+    )
+        .into_response();
+
+    for (name, value) in response.headers {
+        http_response.headers_mut().insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+
+    http_response
+}
+
+async fn events(ws: WebSocketUpgrade, State(state): State<MockState>) -> impl IntoResponse {
+    state.connections.fetch_add(1, Ordering::SeqCst);
+    let frames = state
+        .sessions
+        .lock()
+        .unwrap()
+        .pop_front()
+        .unwrap_or_default();
+
+    ws.on_upgrade(move |socket| send_frames(socket, frames))
+}
+
+async fn send_frames(mut socket: WebSocket, frames: Vec<String>) {
+    for frame in frames {
+        if socket.send(Message::Text(frame)).await.is_err() {
+            return;
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Connects to the redis at `TEST_REDIS_URL`
+///
+/// The tests that need redis are ignored by default, run them with `cargo test -- --ignored`
+pub async fn test_db() -> Database {
+    let url = std::env::var("TEST_REDIS_URL")
+        .expect("TEST_REDIS_URL must point at a scratch redis to run the ignored tests");
+
+    Database::connect(&url).await
+}
+
+/// A client pointed at the mock
+pub fn test_client(db: &Database, mock: &MockBackpackTF) -> BackpackTF {
+    let mut bptf = BackpackTF::new("key".to_owned(), "token".to_owned(), db.clone()).unwrap();
+    bptf.set_endpoints(mock.base_url.clone(), mock.ws_url.clone());
+    bptf
+}
+
+/// A notifier without webhooks that holds suggestions moving more than 50%
+pub fn test_notifier(db: &Database) -> Notifier {
+    Notifier::new(
+        db.clone(),
+        vec![],
+        None,
+        5.0,
+        0,
+        PriceConfig::new(
+            None,
+            MarginPolicy::new(0, 0.0, WidenDirection::Both),
+            PriceGuard::new(50.0, GuardMode::Hold),
+        ),
+    )
+    .unwrap()
+}
+
+/// Removes the listings a previous run left behind for the item
+pub async fn clear_item(item: &str) {
+    let client = redis::Client::open(std::env::var("TEST_REDIS_URL").unwrap()).unwrap();
+    let mut conn = client.get_multiplexed_tokio_connection().await.unwrap();
+
+    let index_key = format!("item_listings:{}", item);
+    let keys: Vec<String> = conn.smembers(&index_key).await.unwrap();
+    for key in keys {
+        conn.del::<_, ()>(&key).await.unwrap();
+        conn.del::<_, ()>(format!("tombstone:{}", key))
+            .await
+            .unwrap();
+    }
+    conn.del::<_, ()>(&index_key).await.unwrap();
+}
+
+/// A suggestion with one listing on each side
+pub fn suggestion(item: &str, buy: Option<f32>, sell: Option<f32>) -> PriceSuggestion {
+    PriceSuggestion {
+        item: item.to_owned(),
+        buy,
+        sell,
+        buy_listings: 1,
+        sell_listings: 1,
+        time: 1700000000,
+        margin: None,
+    }
+}
+
+pub fn now() -> u32 {
+    Utc::now().timestamp() as u32
+}
+
+/// A listing the way the snapshot endpoint returns it
+pub fn snapshot_listing(
+    steamid: &str,
+    intent: &str,
+    price: f32,
+    asset_id: Option<u64>,
+    defindex: u32,
+    bump: u32,
+) -> Value {
+    json!({
+        "steamid": steamid,
+        "offers": 1,
+        "buyout": 1,
+        "details": "",
+        "intent": intent,
+        "timestamp": bump,
+        "price": price,
+        "item": {
+            "id": asset_id,
+            "defindex": defindex,
+            "quality": 6,
+        },
+        "bump": bump,
+        "userAgent": {
+            "lastPulse": bump,
+            "client": "mock",
+        },
+    })
+}
+
+pub fn snapshot_body(created_at: u32, listings: Vec<Value>) -> Value {
+    json!({
+        "listings": listings,
+        "createdAt": created_at,
+    })
+}
+
+/// A `listing-update` websocket event of a bot listing
+pub fn listing_update(
+    steamid: &str,
+    intent: &str,
+    price: f32,
+    asset_id: u64,
+    item: &str,
+    defindex: u32,
+    bumped_at: u32,
+) -> Value {
+    json!({
+        "event": "listing-update",
+        "payload": {
+            "id": format!("440_{}", asset_id),
+            "steamid": steamid,
+            "appid": 440,
+            "details": null,
+            "currencies": { "metal": price },
+            "intent": intent,
+            "listedAt": bumped_at,
+            "bumpedAt": bumped_at,
+            "count": 1,
+            "status": "active",
+            "source": "userAgent",
+            "item": {
+                "id": asset_id,
+                "name": item,
+                "defindex": defindex,
+            },
+            "value": { "raw": price },
+        },
+    })
+}
+
+/// A `listing-delete` websocket event of a sell listing
+pub fn listing_delete(steamid: &str, asset_id: u64, item: &str, defindex: u32) -> Value {
+    json!({
+        "event": "listing-delete",
+        "payload": {
+            "id": format!("440_{}", asset_id),
+            "steamid": steamid,
+            "intent": "sell",
+            "item": {
+                "id": asset_id,
+                "name": item,
+                "defindex": defindex,
+            },
+        },
+    })
+}
@@ -0,0 +1,67 @@
+use pricer::event::{listing_id, EventListingDeletion};
+use serde_json::json;
+
+fn deletion(
+    id: &str,
+    steamid: Option<&str>,
+    intent: Option<&str>,
+    asset_id: u64,
+) -> EventListingDeletion {
+    serde_json::from_value(json!({
+        "id": id,
+        "steamid": steamid,
+        "intent": intent,
+        "item": {
+            "id": asset_id,
+            "name": "Mann Co. Supply Crate Key",
+            "defindex": 5021,
+        },
+    }))
+    .unwrap()
+}
+
+#[test]
+fn sell_deletions_use_the_asset_id() {
+    let expected = listing_id(
+        "sell",
+        "76561198000000000",
+        Some(1234),
+        "Mann Co. Supply Crate Key",
+    );
+
+    let listing = deletion("440_1234", Some("76561198000000000"), Some("sell"), 1234);
+    assert_eq!(listing.canonical_id(), expected);
+
+    // the steamid doesn't matter for sell listings
+    let listing = deletion("440_1234", None, Some("sell"), 1234);
+    assert_eq!(listing.canonical_id(), expected);
+}
+
+#[test]
+fn buy_deletions_use_the_steamid_and_item_name() {
+    let expected = listing_id(
+        "buy",
+        "76561198000000000",
+        None,
+        "Mann Co. Supply Crate Key",
+    );
+
+    // backpack.tf sends buy listings with an unrelated id and no asset id
+    let listing = deletion(
+        "440_76561198000000000_0123456789abcdef",
+        Some("76561198000000000"),
+        Some("buy"),
+        0,
+    );
+    assert_eq!(listing.canonical_id(), expected);
+    assert_ne!(listing.canonical_id(), listing.id);
+}
+
+#[test]
+fn incomplete_deletions_keep_the_backpack_tf_id() {
+    let listing = deletion("440_76561198000000000_abc", None, Some("buy"), 0);
+    assert_eq!(listing.canonical_id(), listing.id);
+
+    let listing = deletion("440_1234", Some("76561198000000000"), None, 1234);
+    assert_eq!(listing.canonical_id(), listing.id);
+}
@@ -0,0 +1,221 @@
+use std::fs::File;
+
+use parquet::{
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+use pricer::export::{write_rows, Cell, ColumnType, ExportFormat, ExportRow, ListingRow, PriceRow};
+use serde_json::Value;
+
+fn listing_rows() -> Vec<ListingRow> {
+    vec![
+        ListingRow {
+            sku: "5021;6".to_owned(),
+            item: "Mann Co. Supply Crate Key".to_owned(),
+            key: "440_76561198000000001_1234".to_owned(),
+            id: Some("440_1234".to_owned()),
+            intent: "sell".to_owned(),
+            steamid: "76561198000000001".to_owned(),
+            price: 55.11,
+            bumped_at: 1700000000,
+            asset_id: Some(1234),
+            defindex: 5021,
+            details: Some("Fast trades, \"quoted\", with a comma".to_owned()),
+        },
+        // every nullable column is empty
+        ListingRow {
+            sku: "5021;6".to_owned(),
+            item: "Mann Co. Supply Crate Key".to_owned(),
+            key: "440_76561198000000002_buy".to_owned(),
+            id: None,
+            intent: "buy".to_owned(),
+            steamid: "76561198000000002".to_owned(),
+            price: 54.66,
+            bumped_at: 1700000060,
+            asset_id: None,
+            defindex: 5021,
+            details: None,
+        },
+    ]
+}
+
+fn price_rows() -> Vec<PriceRow> {
+    vec![
+        PriceRow {
+            sku: "5021;6".to_owned(),
+            item: "Mann Co. Supply Crate Key".to_owned(),
+            time: 1700000000,
+            buy: Some(54.66),
+            sell: Some(55.11),
+            buy_listings: 12,
+            sell_listings: 30,
+        },
+        PriceRow {
+            sku: "5021;6".to_owned(),
+            item: "Mann Co. Supply Crate Key".to_owned(),
+            time: 1700000300,
+            buy: None,
+            sell: Some(55.22),
+            buy_listings: 0,
+            sell_listings: 31,
+        },
+        PriceRow {
+            sku: "5021;6".to_owned(),
+            item: "Mann Co. Supply Crate Key".to_owned(),
+            time: 1700000600,
+            buy: None,
+            sell: None,
+            buy_listings: 0,
+            sell_listings: 0,
+        },
+    ]
+}
+
+fn expected_cells<R: ExportRow>(rows: &[R]) -> Vec<Vec<Cell>> {
+    rows.iter().map(|row| row.cells()).collect()
+}
+
+fn write<R: ExportRow>(rows: &[R], format: ExportFormat) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_rows(rows, format, &mut output).unwrap();
+    output
+}
+
+fn read_csv<R: ExportRow>(output: &[u8]) -> Vec<Vec<Cell>> {
+    let mut reader = csv::Reader::from_reader(output);
+
+    let header: Vec<String> = reader
+        .headers()
+        .unwrap()
+        .iter()
+        .map(str::to_owned)
+        .collect();
+    let names: Vec<&str> = R::COLUMNS.iter().map(|column| column.name).collect();
+    assert_eq!(header, names);
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            R::COLUMNS
+                .iter()
+                .zip(record.iter())
+                .map(|(column, value)| match column.kind {
+                    _ if value.is_empty() && column.nullable => Cell::Null,
+                    ColumnType::Text => Cell::Text(value.to_owned()),
+                    ColumnType::Integer => Cell::Integer(value.parse().unwrap()),
+                    ColumnType::Float => Cell::Float(value.parse().unwrap()),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn read_jsonl<R: ExportRow>(output: &[u8]) -> Vec<Vec<Cell>> {
+    String::from_utf8(output.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let value: Value = serde_json::from_str(line).unwrap();
+            let object = value.as_object().unwrap();
+            assert_eq!(object.len(), R::COLUMNS.len());
+
+            R::COLUMNS
+                .iter()
+                .map(|column| match (&object[column.name], column.kind) {
+                    (Value::Null, _) => Cell::Null,
+                    (value, ColumnType::Text) => Cell::Text(value.as_str().unwrap().to_owned()),
+                    (value, ColumnType::Integer) => Cell::Integer(value.as_i64().unwrap()),
+                    (value, ColumnType::Float) => Cell::Float(value.as_f64().unwrap() as f32),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn read_parquet<R: ExportRow>(output: &[u8], name: &str) -> Vec<Vec<Cell>> {
+    let path = std::env::temp_dir().join(format!(
+        "pricer-export-test-{}-{}.parquet",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, output).unwrap();
+
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let rows: Vec<Vec<Cell>> = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| {
+            let row = row.unwrap();
+            R::COLUMNS
+                .iter()
+                .zip(row.get_column_iter())
+                .map(|(column, (name, field))| {
+                    assert_eq!(name, column.name);
+                    match field {
+                        Field::Null => Cell::Null,
+                        Field::Str(text) => Cell::Text(text.clone()),
+                        Field::Long(integer) => Cell::Integer(*integer),
+                        Field::Float(float) => Cell::Float(*float),
+                        field => panic!("Unexpected parquet field {:?}", field),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    std::fs::remove_file(&path).unwrap();
+    rows
+}
+
+#[test]
+fn csv_round_trips() {
+    let listings = listing_rows();
+    let output = write(&listings, ExportFormat::Csv);
+    assert_eq!(read_csv::<ListingRow>(&output), expected_cells(&listings));
+
+    let prices = price_rows();
+    let output = write(&prices, ExportFormat::Csv);
+    assert_eq!(read_csv::<PriceRow>(&output), expected_cells(&prices));
+}
+
+#[test]
+fn jsonl_round_trips() {
+    let listings = listing_rows();
+    let output = write(&listings, ExportFormat::Jsonl);
+    assert_eq!(read_jsonl::<ListingRow>(&output), expected_cells(&listings));
+
+    let prices = price_rows();
+    let output = write(&prices, ExportFormat::Jsonl);
+    assert_eq!(read_jsonl::<PriceRow>(&output), expected_cells(&prices));
+
+    // nulls are written as JSON nulls rather than left out
+    let line = String::from_utf8(output).unwrap();
+    assert!(line.lines().last().unwrap().contains("\"buy\":null"));
+}
+
+#[test]
+fn parquet_round_trips() {
+    let listings = listing_rows();
+    let output = write(&listings, ExportFormat::Parquet);
+    assert_eq!(
+        read_parquet::<ListingRow>(&output, "listings"),
+        expected_cells(&listings)
+    );
+
+    let prices = price_rows();
+    let output = write(&prices, ExportFormat::Parquet);
+    assert_eq!(
+        read_parquet::<PriceRow>(&output, "prices"),
+        expected_cells(&prices)
+    );
+}
+
+#[test]
+fn empty_exports_are_readable() {
+    let rows: Vec<PriceRow> = Vec::new();
+
+    assert!(read_csv::<PriceRow>(&write(&rows, ExportFormat::Csv)).is_empty());
+    assert!(read_jsonl::<PriceRow>(&write(&rows, ExportFormat::Jsonl)).is_empty());
+    assert!(read_parquet::<PriceRow>(&write(&rows, ExportFormat::Parquet), "empty").is_empty());
+}
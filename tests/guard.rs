@@ -0,0 +1,130 @@
+mod common;
+
+use common::{
+    clear_item, now, snapshot_body, snapshot_listing, suggestion, test_db, test_notifier,
+};
+use pricer::{
+    db::Database,
+    guard::{BreakerReason, BreakerTrip, GuardMode, GuardReason, HeldPrice, PriceGuard},
+    margin::{MarginPolicy, WidenDirection},
+    pricing::{get_suggestion, PriceConfig},
+    types::ListingResponse,
+};
+
+#[test]
+fn guard_stops_crossed_and_large_changes() {
+    let guard = PriceGuard::new(50.0, GuardMode::Hold);
+    let old = suggestion("Item", Some(10.0), Some(11.0));
+
+    assert_eq!(
+        guard.check(Some(&old), &suggestion("Item", Some(12.0), Some(11.0))),
+        Some(GuardReason::Crossed)
+    );
+    assert_eq!(
+        guard.check(None, &suggestion("Item", Some(12.0), Some(11.0))),
+        Some(GuardReason::Crossed)
+    );
+    assert_eq!(
+        guard.check(Some(&old), &suggestion("Item", Some(10.0), Some(22.0))),
+        Some(GuardReason::Change { percent: 100.0 })
+    );
+
+    // within the limit, or a side that disappears
+    assert_eq!(
+        guard.check(Some(&old), &suggestion("Item", Some(12.0), Some(13.0))),
+        None
+    );
+    assert_eq!(
+        guard.check(Some(&old), &suggestion("Item", None, Some(12.0))),
+        None
+    );
+    assert_eq!(
+        PriceGuard::new(0.0, GuardMode::Hold)
+            .check(Some(&old), &suggestion("Item", Some(10.0), Some(22.0))),
+        None
+    );
+}
+
+/// Stores a snapshot with one buy and one sell listing of the item
+async fn store_snapshot(db: &Database, item: &str, buy: f32, sell: f32) {
+    let created_at = now();
+    let snapshot: ListingResponse = serde_json::from_value(snapshot_body(
+        created_at,
+        vec![
+            snapshot_listing("76561198000000051", "buy", buy, None, 9005, created_at),
+            snapshot_listing(
+                "76561198000000052",
+                "sell",
+                sell,
+                Some(95001),
+                9005,
+                created_at,
+            ),
+        ],
+    ))
+    .unwrap();
+
+    db.update_listings_from_snapshot(snapshot.listings, item, created_at)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn review_queue_and_circuit_breaker() {
+    let db = test_db().await;
+    let item = "Integration Test Guard Item";
+
+    let held = HeldPrice {
+        item: item.to_owned(),
+        old: None,
+        new: suggestion(item, Some(1.0), Some(2.0)),
+        reason: GuardReason::Crossed,
+        time: 1700000000,
+    };
+    db.hold_price(&held).await.unwrap();
+    assert_eq!(db.get_held_price(item).await.unwrap(), Some(held.clone()));
+
+    let notifier = test_notifier(&db);
+    assert_eq!(notifier.approve(item).await.unwrap(), Some(held.clone()));
+    assert_eq!(db.get_price(item).await.unwrap(), Some(held.new));
+    assert_eq!(db.get_held_price(item).await.unwrap(), None);
+    assert_eq!(notifier.approve(item).await.unwrap(), None);
+
+    db.reset_breaker().await.unwrap();
+    let trip = BreakerTrip::new(BreakerReason::Manual, "testing".to_owned());
+    assert!(db.trip_breaker(&trip).await.unwrap());
+    assert!(!db
+        .trip_breaker(&BreakerTrip::new(
+            BreakerReason::MassDeletion,
+            "later".to_owned()
+        ))
+        .await
+        .unwrap());
+    assert_eq!(db.get_breaker().await.unwrap(), Some(trip));
+    assert!(db.reset_breaker().await.unwrap());
+    assert_eq!(db.get_breaker().await.unwrap(), None);
+
+    // items that were never published get the same treatment when they are looked up
+    let unpublished = "Integration Test Unpublished Guard Item";
+    clear_item(unpublished).await;
+    let config = PriceConfig::new(
+        None,
+        MarginPolicy::new(0, 0.0, WidenDirection::Both),
+        PriceGuard::new(50.0, GuardMode::Hold),
+    );
+    store_snapshot(&db, unpublished, 3.0, 2.0).await;
+    let crossed = get_suggestion(&db, &config, unpublished).await.unwrap();
+    assert_eq!((crossed.buy, crossed.sell), (None, None));
+    assert_eq!((crossed.buy_listings, crossed.sell_listings), (1, 1));
+
+    store_snapshot(&db, unpublished, 2.0, 3.0).await;
+    let priced = get_suggestion(&db, &config, unpublished).await.unwrap();
+    assert_eq!((priced.buy, priced.sell), (Some(2.0), Some(3.0)));
+
+    let trip = BreakerTrip::new(BreakerReason::Manual, "testing".to_owned());
+    assert!(db.trip_breaker(&trip).await.unwrap());
+    let frozen = get_suggestion(&db, &config, unpublished).await.unwrap();
+    assert_eq!((frozen.buy, frozen.sell), (None, None));
+    assert!(db.reset_breaker().await.unwrap());
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::suggestion;
+use pricer::{
+    margin::{MarginPolicy, MarginStatus, UnmetReason, WidenDirection},
+    pricing::KEY_ITEM,
+};
+
+fn assert_close(value: Option<f32>, expected: f32) {
+    let value = value.unwrap();
+    assert!(
+        (value - expected).abs() < 0.001,
+        "{} != {}",
+        value,
+        expected
+    );
+}
+
+#[test]
+fn margin_in_scrap_widens_in_every_direction() {
+    // 2 scrap = 0.22 ref
+    let mut both = suggestion("Item", Some(10.0), Some(10.11));
+    MarginPolicy::new(2, 0.0, WidenDirection::Both).apply(&mut both);
+    assert_close(both.buy, 9.945);
+    assert_close(both.sell, 10.165);
+    assert_eq!(
+        both.margin,
+        Some(MarginStatus::Widened {
+            buy: 10.0,
+            sell: 10.11
+        })
+    );
+
+    let mut buy = suggestion("Item", Some(10.0), Some(10.11));
+    MarginPolicy::new(2, 0.0, WidenDirection::Buy).apply(&mut buy);
+    assert_close(buy.buy, 9.89);
+    assert_close(buy.sell, 10.11);
+
+    let mut sell = suggestion("Item", Some(10.0), Some(10.11));
+    MarginPolicy::new(2, 0.0, WidenDirection::Sell).apply(&mut sell);
+    assert_close(sell.buy, 10.0);
+    assert_close(sell.sell, 10.22);
+}
+
+#[test]
+fn margin_in_percent_of_the_buy_price() {
+    let mut sell = suggestion("Item", Some(50.0), Some(51.0));
+    MarginPolicy::new(0, 5.0, WidenDirection::Sell).apply(&mut sell);
+    assert_close(sell.sell, 52.5);
+
+    let mut buy = suggestion("Item", Some(50.0), Some(52.5));
+    MarginPolicy::new(1, 5.0, WidenDirection::Buy).apply(&mut buy);
+    assert_eq!(buy.margin, Some(MarginStatus::Met));
+    assert_eq!(buy.buy, Some(50.0));
+
+    let mut buy = suggestion("Item", Some(50.0), Some(51.0));
+    MarginPolicy::new(1, 5.0, WidenDirection::Buy).apply(&mut buy);
+    assert_close(buy.buy, 51.0 / 1.05);
+}
+
+#[test]
+fn margin_reports_what_it_cant_fix() {
+    let policy = MarginPolicy::new(2, 10.0, WidenDirection::Buy);
+
+    let mut crossed = suggestion("Item", Some(11.0), Some(10.0));
+    policy.apply(&mut crossed);
+    assert_eq!(
+        crossed.margin,
+        Some(MarginStatus::Unmet {
+            reason: UnmetReason::Crossed
+        })
+    );
+    assert_eq!(crossed.buy, Some(11.0));
+
+    let mut cheap = suggestion("Item", Some(0.11), Some(0.22));
+    policy.apply(&mut cheap);
+    assert_eq!(
+        cheap.margin,
+        Some(MarginStatus::Unmet {
+            reason: UnmetReason::BuyTooLow
+        })
+    );
+    assert_eq!(cheap.buy, Some(0.11));
+
+    let mut one_sided = suggestion("Item", None, Some(10.0));
+    policy.apply(&mut one_sided);
+    assert_eq!(one_sided.margin, None);
+
+    let mut disabled = suggestion("Item", Some(10.0), Some(10.0));
+    MarginPolicy::new(0, 0.0, WidenDirection::Both).apply(&mut disabled);
+    assert_eq!(disabled.margin, None);
+}
+
+#[test]
+fn key_is_left_alone() {
+    let mut key = suggestion(KEY_ITEM, Some(55.0), Some(55.11));
+    MarginPolicy::new(2, 10.0, WidenDirection::Both).apply(&mut key);
+    assert_eq!(key.buy, Some(55.0));
+    assert_eq!(key.sell, Some(55.11));
+    assert_eq!(key.margin, None);
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::{test_db, test_notifier};
+use pricer::{
+    guard::{GuardMode, PriceGuard},
+    items::TrackedItem,
+    margin::{MarginPolicy, WidenDirection},
+    notifier::{PriceChange, OVERRIDE_REMOVE_EVENT, OVERRIDE_SET_EVENT},
+    pricing::{get_item_price, ItemCurrencies, PriceConfig, PriceOverride, PriceSuggestion},
+};
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn overrides_take_precedence_until_removed() {
+    let db = test_db().await;
+    let item = TrackedItem {
+        name: "Integration Test Override Item".to_owned(),
+        sku: Some("5001;6".to_owned()),
+    };
+    db.add_tracked_items(std::slice::from_ref(&item))
+        .await
+        .unwrap();
+    db.remove_override(&item.name).await.unwrap();
+
+    let buy = ItemCurrencies {
+        keys: 0,
+        metal: 0.33,
+    };
+    let sell = ItemCurrencies {
+        keys: 0,
+        metal: 0.44,
+    };
+    let price = PriceOverride::new(&item.name, buy, sell, "tester", "dupe scare", None).unwrap();
+    db.set_override(&price).await.unwrap();
+
+    let config = PriceConfig::new(
+        None,
+        MarginPolicy::new(0, 0.0, WidenDirection::Both),
+        PriceGuard::new(50.0, GuardMode::Hold),
+    );
+    let item_price = get_item_price(&db, &config, &item, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(item_price.overridden);
+    assert_eq!(item_price.buy, buy);
+    assert_eq!(item_price.sell, sell);
+    assert!(db
+        .get_overrides()
+        .await
+        .unwrap()
+        .iter()
+        .any(|price| price.item == item.name));
+
+    assert!(db.remove_override(&item.name).await.unwrap());
+    assert!(!db.remove_override(&item.name).await.unwrap());
+    assert_eq!(db.get_override(&item.name).await.unwrap(), None);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn expired_overrides_are_ignored() {
+    let db = test_db().await;
+    let item = "Integration Test Expired Override Item";
+
+    let mut price = PriceOverride::new(
+        item,
+        ItemCurrencies {
+            keys: 0,
+            metal: 1.0,
+        },
+        ItemCurrencies {
+            keys: 0,
+            metal: 2.0,
+        },
+        "tester",
+        "promotion",
+        Some(chrono::Utc::now().timestamp() + 3600),
+    )
+    .unwrap();
+    db.set_override(&price).await.unwrap();
+    assert!(db.get_override(item).await.unwrap().is_some());
+
+    price.expires_at = Some(chrono::Utc::now().timestamp() - 1);
+    db.set_override(&price).await.unwrap();
+    assert_eq!(db.get_override(item).await.unwrap(), None);
+    assert!(db
+        .remove_expired_overrides()
+        .await
+        .unwrap()
+        .contains(&price));
+    assert!(!db.remove_override(item).await.unwrap());
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn override_changes_are_published() {
+    let db = test_db().await;
+    let item = TrackedItem {
+        name: "Integration Test Published Override Item".to_owned(),
+        sku: Some("5001;6".to_owned()),
+    };
+    db.add_tracked_items(std::slice::from_ref(&item))
+        .await
+        .unwrap();
+    db.remove_override(&item.name).await.unwrap();
+
+    let suggestion = PriceSuggestion {
+        item: item.name.clone(),
+        buy: Some(0.11),
+        sell: Some(0.22),
+        buy_listings: 3,
+        sell_listings: 4,
+        time: 1700000000,
+        margin: None,
+    };
+    db.set_price(&suggestion).await.unwrap();
+
+    let notifier = test_notifier(&db);
+    let since = chrono::Utc::now().timestamp();
+
+    let buy = ItemCurrencies {
+        keys: 0,
+        metal: 0.33,
+    };
+    let sell = ItemCurrencies {
+        keys: 0,
+        metal: 0.44,
+    };
+    let price = PriceOverride::new(&item.name, buy, sell, "tester", "dupe scare", None).unwrap();
+    notifier.set_override(&price).await.unwrap();
+    assert!(notifier.remove_override(&item.name).await.unwrap());
+    assert!(!notifier.remove_override(&item.name).await.unwrap());
+
+    let changes: Vec<PriceChange> = db
+        .get_price_history(Some(since), None)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|change| change.item == item.name)
+        .collect();
+    assert_eq!(changes.len(), 2);
+
+    let set = &changes[0];
+    assert_eq!(set.event, OVERRIDE_SET_EVENT);
+    assert!(set.overridden);
+    assert_eq!(set.old, Some(suggestion.clone()));
+    assert_eq!((set.new.buy, set.new.sell), (Some(0.33), Some(0.44)));
+    assert_eq!((set.new.buy_listings, set.new.sell_listings), (3, 4));
+    let set_price = set.price.as_ref().unwrap();
+    assert!(set_price.overridden);
+    assert_eq!((set_price.buy, set_price.sell), (buy, sell));
+
+    let removed = &changes[1];
+    assert_eq!(removed.event, OVERRIDE_REMOVE_EVENT);
+    assert!(!removed.overridden);
+    assert_eq!(removed.price, None);
+    assert_eq!(removed.old.as_ref().unwrap().sell, Some(0.44));
+    assert_eq!(removed.new, suggestion);
+}
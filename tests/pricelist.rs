@@ -0,0 +1,200 @@
+mod common;
+
+use common::{test_db, test_notifier};
+use pricer::{
+    guard::{GuardMode, PriceGuard},
+    margin::{MarginPolicy, WidenDirection},
+    pricelist::{
+        build_pricelist, import_pricelist, normalize_sku, parse_pricelist, PRICELIST_AUTHOR,
+    },
+    pricing::{ItemCurrencies, PriceConfig, PriceOverride},
+};
+
+#[test]
+fn parses_array_pricelists() {
+    let entries = parse_pricelist(
+        r#"[
+            { "sku": "5021;6", "name": "Mann Co. Supply Crate Key", "autoprice": true },
+            { "sku": "5002;6", "enabled": false, "intent": 1 }
+        ]"#,
+    )
+    .unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].sku, "5021;6");
+    assert_eq!(
+        entries[0].name.as_deref(),
+        Some("Mann Co. Supply Crate Key")
+    );
+    // missing fields get the tf2autobot defaults
+    assert_eq!(entries[1].name, None);
+    assert!(!entries[1].enabled);
+    assert!(entries[1].autoprice);
+    assert_eq!(
+        (entries[1].min, entries[1].max, entries[1].intent),
+        (0, 1, 1)
+    );
+    assert_eq!(entries[1].group, "all");
+}
+
+#[test]
+fn parses_keyed_pricelists() {
+    let mut entries = parse_pricelist(
+        r#"{
+            "5021;6": { "sku": "5021;6", "max": 10 },
+            "5002;6": { "sku": "5002;6", "buy": { "keys": 0, "metal": 60.11 } }
+        }"#,
+    )
+    .unwrap();
+    entries.sort_by(|a, b| a.sku.cmp(&b.sku));
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].sku, "5002;6");
+    assert_eq!(entries[0].buy.metal, 60.11);
+    assert_eq!(entries[1].sku, "5021;6");
+    assert_eq!(entries[1].max, 10);
+
+    assert!(parse_pricelist(r#""5021;6""#).is_err());
+    assert!(parse_pricelist(r#"[{ "name": "No sku" }]"#).is_err());
+}
+
+#[test]
+fn keeps_unknown_pricelist_fields() {
+    let content = r#"[{ "sku": "5021;6", "customField": { "nested": [1, 2] }, "promoted": 1 }]"#;
+    let entries = parse_pricelist(content).unwrap();
+
+    assert_eq!(entries[0].promoted, 1);
+    assert_eq!(entries[0].extra.len(), 1);
+    assert_eq!(
+        entries[0].extra["customField"],
+        serde_json::json!({ "nested": [1, 2] })
+    );
+
+    // the unknown fields are written back next to the known ones
+    let written = serde_json::to_value(&entries[0]).unwrap();
+    assert_eq!(written["customField"]["nested"][1], 2);
+    assert_eq!(written["isPartialPriced"], false);
+    assert_eq!(parse_pricelist(&format!("[{}]", written)).unwrap(), entries);
+}
+
+#[test]
+fn normalizes_skus() {
+    assert_eq!(normalize_sku("5021;6").as_deref(), Some("5021;6"));
+    assert_eq!(
+        normalize_sku("30743;5;u703;uncraftable").as_deref(),
+        Some("30743;5;u703;uncraftable")
+    );
+    assert_eq!(
+        normalize_sku("30743;5;uncraftable;u703").as_deref(),
+        Some("30743;5;u703;uncraftable")
+    );
+    assert_eq!(normalize_sku("not a sku"), None);
+    assert_eq!(normalize_sku(""), None);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn pricelist_import_and_export() {
+    let db = test_db().await;
+    let item = "Integration Test Pricelist Item";
+
+    let entries = parse_pricelist(&format!(
+        r#"{{
+            "99901;6": {{ "sku": "99901;6", "name": "{}", "autoprice": false, "min": 1, "max": 5,
+                "buy": {{ "keys": 0, "metal": 0.11 }}, "sell": {{ "keys": 1, "metal": 0.22 }},
+                "time": 1700000000, "customField": true }},
+            "not a sku": {{ "sku": "not a sku", "name": "Invalid" }}
+        }}"#,
+        item
+    ))
+    .unwrap();
+
+    let stats = import_pricelist(&db, &test_notifier(&db), entries, false)
+        .await
+        .unwrap();
+    assert_eq!(stats.entries, 2);
+    assert_eq!(stats.tracked, 1);
+    assert_eq!(stats.overrides, 1);
+    assert_eq!(stats.kept_overrides, 0);
+    assert_eq!(stats.skipped, 1);
+
+    let tracked = db.find_tracked_item("99901;6").await.unwrap().unwrap();
+    assert_eq!(tracked.name, item);
+
+    let price = db.get_override(item).await.unwrap().unwrap();
+    assert_eq!(price.sell.keys, 1);
+    assert_eq!(price.time, 1700000000);
+
+    let config = PriceConfig::new(
+        None,
+        MarginPolicy::new(0, 0.0, WidenDirection::Both),
+        PriceGuard::new(50.0, GuardMode::Hold),
+    );
+    let pricelist = build_pricelist(&db, &config).await.unwrap();
+    let entry = pricelist
+        .iter()
+        .find(|entry| entry.name.as_deref() == Some(item))
+        .unwrap();
+    assert!(!entry.autoprice);
+    assert_eq!((entry.min, entry.max), (1, 5));
+    assert_eq!(entry.buy, price.buy);
+    assert_eq!(entry.sell, price.sell);
+    assert_eq!(entry.extra["customField"], true);
+}
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn pricelist_import_keeps_manual_overrides() {
+    let db = test_db().await;
+    let notifier = test_notifier(&db);
+    let item = "Integration Test Pricelist Manual Item";
+
+    let manual = PriceOverride::new(
+        item,
+        ItemCurrencies {
+            keys: 0,
+            metal: 1.0,
+        },
+        ItemCurrencies {
+            keys: 0,
+            metal: 2.0,
+        },
+        "tester",
+        "dupe scare",
+        None,
+    )
+    .unwrap();
+    notifier.set_override(&manual).await.unwrap();
+
+    let content = format!(
+        r#"[{{ "sku": "99902;6", "name": "{}", "autoprice": false,
+            "buy": {{ "keys": 0, "metal": 3 }}, "sell": {{ "keys": 0, "metal": 4 }} }}]"#,
+        item
+    );
+
+    let stats = import_pricelist(&db, &notifier, parse_pricelist(&content).unwrap(), false)
+        .await
+        .unwrap();
+    assert_eq!(
+        (stats.tracked, stats.overrides, stats.kept_overrides),
+        (1, 0, 1)
+    );
+    assert_eq!(db.get_override(item).await.unwrap(), Some(manual));
+
+    let stats = import_pricelist(&db, &notifier, parse_pricelist(&content).unwrap(), true)
+        .await
+        .unwrap();
+    assert_eq!(
+        (stats.tracked, stats.overrides, stats.kept_overrides),
+        (1, 1, 0)
+    );
+    let imported = db.get_override(item).await.unwrap().unwrap();
+    assert_eq!(imported.author, PRICELIST_AUTHOR);
+    assert_eq!(imported.sell.metal, 4.0);
+
+    // overrides of earlier imports get replaced without --force
+    let stats = import_pricelist(&db, &notifier, parse_pricelist(&content).unwrap(), false)
+        .await
+        .unwrap();
+    assert_eq!((stats.overrides, stats.kept_overrides), (1, 0));
+}
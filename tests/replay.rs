@@ -0,0 +1,100 @@
+mod common;
+
+use std::io::Write;
+
+use common::{clear_item, listing_delete, listing_update, now, test_db};
+use flate2::{write::GzEncoder, Compression};
+use pricer::{
+    archive::ArchiveRecord,
+    replay::{replay, ReplayOptions},
+};
+use redis::AsyncCommands;
+use serde_json::json;
+
+#[tokio::test]
+#[ignore = "needs redis at TEST_REDIS_URL"]
+async fn replay_uses_the_record_time() {
+    let db = test_db().await;
+    let item = "Integration Test Replay Item";
+    clear_item(item).await;
+
+    // a value the store can't read doesn't stop the pruning
+    let client = redis::Client::open(std::env::var("TEST_REDIS_URL").unwrap()).unwrap();
+    let mut conn = client.get_multiplexed_tokio_connection().await.unwrap();
+    let corrupt_key = "listing:integration-test-replay-corrupt";
+    conn.set::<_, _, ()>(corrupt_key, "not a listing")
+        .await
+        .unwrap();
+
+    // a week old archive, the wall clock would tombstone the relist and expire everything
+    let start = now() - 7 * 86400;
+    let frames = [
+        (
+            start,
+            json!([
+                listing_update("76561198000000001", "sell", 10.0, 501, item, 5050, start),
+                listing_update(
+                    "76561198000000002",
+                    "sell",
+                    11.0,
+                    502,
+                    item,
+                    5050,
+                    start - 2 * 86400
+                ),
+            ]),
+        ),
+        (
+            start + 60,
+            json!([listing_delete("76561198000000001", 501, item, 5050)]),
+        ),
+        (
+            start + 120,
+            json!([listing_update(
+                "76561198000000001",
+                "sell",
+                12.0,
+                501,
+                item,
+                5050,
+                start + 120
+            )]),
+        ),
+    ];
+
+    let path = std::env::temp_dir().join(format!("pricer-replay-test-{}.jsonl.gz", now()));
+    let mut file = GzEncoder::new(
+        std::fs::File::create(&path).unwrap(),
+        Compression::default(),
+    );
+    for (received_at, frame) in frames {
+        let record = ArchiveRecord::Websocket {
+            received_at: received_at as i64 * 1000,
+            frame: frame.to_string(),
+        };
+        writeln!(file, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+    }
+    file.finish().unwrap();
+
+    let stats = replay(
+        &db,
+        &ReplayOptions {
+            paths: vec![path.clone()],
+            speed: None,
+            items: vec![item.to_owned()],
+        },
+    )
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    conn.del::<_, ()>(corrupt_key).await.unwrap();
+
+    assert_eq!(stats.frames, 3);
+    assert!(stats.pruned >= 1);
+
+    // the relist came after the deletion, the listing bumped two days before the last
+    // record is too old
+    let listings = db.get_listings_for_item(item).await.unwrap();
+    assert_eq!(listings.len(), 1);
+    assert_eq!(listings[0].bumped_at, start + 120);
+    assert_eq!(listings[0].price, 12.0);
+}
@@ -0,0 +1,34 @@
+use pricer::socketio::{event_packet, Packet};
+
+#[test]
+fn parses_engine_io_packets() {
+    assert_eq!(Packet::parse("2"), Some(Packet::Ping));
+    assert_eq!(Packet::parse("3"), Some(Packet::Pong));
+    assert_eq!(Packet::parse("1"), Some(Packet::Close));
+    assert_eq!(
+        Packet::parse("40"),
+        Some(Packet::Connect { namespace: "/" })
+    );
+    assert_eq!(
+        Packet::parse(r#"40{"token":"secret"}"#),
+        Some(Packet::Connect { namespace: "/" })
+    );
+    assert_eq!(
+        Packet::parse(r#"40/admin,{"token":"secret"}"#),
+        Some(Packet::Connect {
+            namespace: "/admin"
+        })
+    );
+    assert_eq!(Packet::parse("41"), Some(Packet::Disconnect));
+    assert_eq!(Packet::parse(r#"42["subscribe"]"#), Some(Packet::Other));
+    assert_eq!(Packet::parse(""), None);
+    assert_eq!(Packet::parse("hello"), None);
+}
+
+#[test]
+fn encodes_events() {
+    assert_eq!(
+        event_packet("price", r#"{"sku":"5021;6"}"#),
+        r#"42["price",{"sku":"5021;6"}]"#
+    );
+}
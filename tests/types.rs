@@ -0,0 +1,32 @@
+use pricer::types::{parse_time, ListingResponse};
+
+#[test]
+fn parses_timestamps_and_dates() {
+    assert_eq!(parse_time("1700000000"), Some(1700000000));
+    assert_eq!(parse_time("-60"), Some(-60));
+    assert_eq!(parse_time("2023-11-14T22:13:20Z"), Some(1700000000));
+    assert_eq!(parse_time("2023-11-14T23:13:20+01:00"), Some(1700000000));
+    assert_eq!(parse_time("yesterday"), None);
+}
+
+#[test]
+fn rejects_timestamps_without_a_millisecond_form() {
+    assert_eq!(
+        parse_time(&(i64::MAX / 1000).to_string()),
+        Some(i64::MAX / 1000)
+    );
+    assert_eq!(parse_time(&(i64::MAX / 1000 + 1).to_string()), None);
+    assert_eq!(parse_time(&(i64::MIN / 1000 - 1).to_string()), None);
+    assert_eq!(parse_time(&i64::MAX.to_string()), None);
+    assert_eq!(parse_time("99999999999999999999"), None);
+}
+
+#[test]
+fn filters_outliers_of_an_empty_book() {
+    let mut response = ListingResponse {
+        listings: vec![],
+        created_at: 1700000000,
+    };
+    response.filter_outliers();
+    assert!(response.listings.is_empty());
+}